@@ -1,89 +1,660 @@
+use std::collections::HashSet;
+use std::future::Future;
 use std::mem;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-use axum::body::{Body, Bytes};
-use axum::extract::{Request, State};
-use axum::http::response::Parts;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::header::{self, HeaderMap};
+use axum::http::{HeaderValue, Method, StatusCode};
 use axum::middleware::{self, Next};
-use axum::response::Response;
-use axum::{Extension, Router};
+use axum::response::{IntoResponse, Response};
+use moka::Expiry;
 use moka::future::Cache;
-use tokio::time::{Duration, Instant};
+use moka::notification::RemovalCause;
+use moka::ops::compute::{CompResult, Op};
+use tokio::time::Duration;
+use tracing::Instrument;
+use xxhash_rust::xxh3::xxh3_64;
 
-use crate::resolver::UpstreamUrlExtension;
+use crate::calendar::Calendar;
+use crate::proxy::Error;
+use crate::store::{DiskStore, StoredCalendar};
 
 const CACHE_AGE_HEADER: &str = "X-Cache-Age";
+const STALE_WARNING: &str = "110 - \"Response is Stale\"";
+const FAILED_REFRESH_WARNING: &str = "111 - \"Revalidation Failed\"";
+
+#[derive(Debug, Clone, Copy)]
+pub struct CacheConfig {
+    /// How long a calendar is served before asking upstream again.
+    /// Once expired, it is still served once more while refreshing in the background.
+    pub ttl: Duration,
+    /// How long an error, or a failed refresh, is cached.
+    pub error_ttl: Duration,
+    /// How long a cached calendar is kept in total, to tell whether the calendar
+    /// changed and to serve it in place of errors.
+    pub retention: Duration,
+    /// Maximum cache size in megabytes.
+    pub max_capacity: u64,
+}
 
 #[derive(Debug, Clone)]
-struct CachedResponse {
-    parts: Parts,
-    body: Bytes,
-    timestamp: Instant,
+struct CachedCalendar {
+    result: Result<Arc<Calendar>, Arc<Error>>,
+    /// Hash of the serialized calendar, to tell whether it changed.
+    hash: u64,
+    /// Size of the serialized calendar, as an estimate for the memory it takes up.
+    size: usize,
+    timestamp: SystemTime,
+    /// When the calendar last changed, which can be long before `timestamp`.
+    last_modified: SystemTime,
+    /// Set if refreshing this calendar failed and it is served stale instead.
+    failed_refresh: Option<SystemTime>,
+}
+
+impl CachedCalendar {
+    fn new(result: Result<Calendar, Error>) -> Self {
+        let serialized = match &result {
+            Ok(calendar) => serde_json::to_vec(calendar).unwrap_or_default(),
+            Err(err) => err.to_string().into_bytes(),
+        };
+
+        let now = SystemTime::now();
+        Self {
+            result: result.map(Arc::new).map_err(Arc::new),
+            hash: xxh3_64(&serialized),
+            size: serialized.len(),
+            timestamp: now,
+            last_modified: now,
+            failed_refresh: None,
+        }
+    }
+
+    fn age(&self) -> Duration {
+        self.timestamp.elapsed().unwrap_or_default()
+    }
+
+    fn is_fresh(&self, config: &CacheConfig) -> bool {
+        match self.failed_refresh {
+            Some(failed) => failed.elapsed().unwrap_or_default() < config.error_ttl,
+            None if self.result.is_ok() => self.age() < config.ttl,
+            None => self.age() < config.error_ttl,
+        }
+    }
+
+    fn to_stored(&self, key: &str) -> Option<StoredCalendar> {
+        Some(StoredCalendar {
+            key: key.to_string(),
+            calendar: Calendar::clone(self.result.as_ref().ok()?),
+            timestamp: self.timestamp,
+            last_modified: self.last_modified,
+            failed_refresh: self.failed_refresh,
+        })
+    }
+
+    fn from_stored(stored: StoredCalendar) -> (String, Self) {
+        let cached = Self {
+            timestamp: stored.timestamp,
+            last_modified: stored.last_modified,
+            failed_refresh: stored.failed_refresh,
+            ..Self::new(Ok(stored.calendar))
+        };
+        (stored.key, cached)
+    }
+}
+
+/// The result of looking up a calendar, along with how fresh it is.
+#[derive(Debug, Clone)]
+pub struct CacheLookup {
+    pub result: Result<Arc<Calendar>, Arc<Error>>,
+    pub freshness: Freshness,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Freshness {
+    last_modified: SystemTime,
+    /// Set if the calendar was served from the cache instead of asking upstream.
+    age: Option<Duration>,
+    warning: Option<&'static str>,
+}
+
+impl Freshness {
+    /// Something built from several calendars is as fresh as the oldest of them.
+    pub fn combine(self, other: Self) -> Self {
+        Self {
+            last_modified: self.last_modified.max(other.last_modified),
+            age: self.age.max(other.age),
+            warning: match (self.warning, other.warning) {
+                (Some(FAILED_REFRESH_WARNING), _) | (_, Some(FAILED_REFRESH_WARNING)) => {
+                    Some(FAILED_REFRESH_WARNING)
+                }
+                (warning, other) => warning.or(other),
+            },
+        }
+    }
+
+    pub fn apply_headers(&self, response: &mut Response) {
+        let headers = response.headers_mut();
+
+        let last_modified = httpdate::fmt_http_date(self.last_modified);
+        headers.insert(
+            header::LAST_MODIFIED,
+            last_modified.parse().expect("header value did not parse"),
+        );
+
+        if let Some(warning) = self.warning {
+            headers.insert(header::WARNING, HeaderValue::from_static(warning));
+        }
+
+        if let Some(age) = self.age {
+            let age = age.as_secs().to_string();
+            headers.insert(
+                CACHE_AGE_HEADER,
+                age.parse().expect("header value did not parse"),
+            );
+        }
+    }
+}
+
+/// Evicts entries once they're older than the retention period, counting from
+/// when they were fetched rather than when they were (re-)inserted.
+struct RetentionExpiry(Duration);
+
+impl Expiry<String, CachedCalendar> for RetentionExpiry {
+    fn expire_after_create(
+        &self,
+        _key: &String,
+        value: &CachedCalendar,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        Some(self.0.saturating_sub(value.age()))
+    }
+
+    fn expire_after_update(
+        &self,
+        _key: &String,
+        value: &CachedCalendar,
+        _updated_at: Instant,
+        _duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        Some(self.0.saturating_sub(value.age()))
+    }
+}
+
+/// Parsed calendars by upstream URL, shared by all requests no matter how they
+/// filter or render the calendar.
+#[derive(Clone)]
+pub struct CalendarCache(Arc<CacheState>);
+
+struct CacheState {
+    cache: Cache<String, CachedCalendar>,
+    config: CacheConfig,
+    store: Option<DiskStore>,
+    /// Keys that are currently being refreshed in the background.
+    refreshing: Mutex<HashSet<String>>,
+}
+
+impl CalendarCache {
+    /// Calendars from `store` are loaded into the cache, and the cache is written back to it.
+    pub async fn new(config: CacheConfig, store: Option<DiskStore>) -> Self {
+        let listener_store = store.clone();
+        let cache = Cache::builder()
+            // Entries are kept beyond their TTL, freshness is checked on every request.
+            .expire_after(RetentionExpiry(config.ttl.max(config.retention)))
+            .eviction_listener(move |key: Arc<String>, _, cause| {
+                if let Some(store) = &listener_store
+                    && cause != RemovalCause::Replaced
+                {
+                    store.remove(&key);
+                }
+            })
+            .max_capacity(config.max_capacity * 1024 * 1024) // Megabytes, weigher measures bytes
+            .weigher(|key: &String, cached: &CachedCalendar| {
+                (mem::size_of::<CachedCalendar>()
+                    .saturating_add(key.len())
+                    .saturating_add(cached.size))
+                .max(1)
+                .try_into()
+                .unwrap_or(u32::MAX)
+            })
+            .build();
+
+        if let Some(store) = &store {
+            let stored = store
+                .load(config.ttl.max(config.retention))
+                .unwrap_or_else(|err| {
+                    tracing::error!(%err, "can't load cache from disk");
+                    Vec::new()
+                });
+
+            for (key, cached) in stored.into_iter().map(CachedCalendar::from_stored) {
+                cache.insert(key, cached).await;
+            }
+        }
+
+        Self(Arc::new(CacheState {
+            cache,
+            config,
+            store,
+            refreshing: Mutex::new(HashSet::new()),
+        }))
+    }
+
+    /// Number of cached calendars, including expired ones still kept around.
+    pub fn entry_count(&self) -> u64 {
+        self.0.cache.entry_count()
+    }
+
+    /// Estimated size of the cache in bytes.
+    pub fn weighted_size(&self) -> u64 {
+        self.0.cache.weighted_size()
+    }
+
+    /// Maximum size of the cache in bytes, `0` if caching is disabled.
+    pub fn max_size(&self) -> u64 {
+        self.0.config.max_capacity * 1024 * 1024
+    }
+
+    /// Whether cached calendars are persisted to disk.
+    pub fn is_persistent(&self) -> bool {
+        self.0.store.is_some()
+    }
+
+    /// Looks up the calendar for `key`, using `fetch` to get it from upstream
+    /// if it isn't cached or expired.
+    #[tracing::instrument(name = "cache", skip_all, fields(hit, revalidating))]
+    pub async fn get<F>(&self, key: String, fetch: F) -> CacheLookup
+    where
+        F: Future<Output = Result<Calendar, Error>> + Send + 'static,
+    {
+        let (cached, cache_hit, revalidating) = match self.0.cache.get(&key).await {
+            Some(cached) if cached.is_fresh(&self.0.config) => (cached, true, false),
+            // Expired calendars are still good enough for this one request, but
+            // there's no use in serving an expired error.
+            Some(cached) if cached.result.is_ok() && cached.age() < self.0.config.retention => {
+                self.0.clone().refresh_in_background(key, fetch);
+                (cached, true, true)
+            }
+            _ => {
+                let result = self.0.refresh(key, fetch).await;
+                let cache_hit = matches!(result, CompResult::Unchanged(_));
+                let cached = result
+                    .into_entry()
+                    .expect("cache entry should exist after computing it")
+                    .into_value();
+                (cached, cache_hit, false)
+            }
+        };
+
+        crate::metrics::record_cache_lookup(cache_hit);
+        tracing::Span::current()
+            .record("hit", cache_hit)
+            .record("revalidating", revalidating);
+
+        let warning = if cached.failed_refresh.is_some() {
+            Some(FAILED_REFRESH_WARNING)
+        } else if revalidating {
+            Some(STALE_WARNING)
+        } else {
+            None
+        };
+
+        CacheLookup {
+            freshness: Freshness {
+                last_modified: cached.last_modified,
+                age: (cache_hit || warning.is_some()).then(|| cached.age()),
+                warning,
+            },
+            result: cached.result,
+        }
+    }
+}
+
+impl CacheState {
+    /// Asks upstream for a new calendar unless the cached one is fresh.
+    ///
+    /// Refreshes of the same key queue up here, so only the first one asks
+    /// upstream and the others get its result.
+    async fn refresh<F>(&self, key: String, fetch: F) -> CompResult<String, CachedCalendar>
+    where
+        F: Future<Output = Result<Calendar, Error>>,
+    {
+        let config = &self.config;
+        let result = self
+            .cache
+            .entry(key)
+            .and_compute_with(|entry| async move {
+                let previous = match entry.map(|entry| entry.into_value()) {
+                    Some(cached) if cached.is_fresh(config) => return Op::Nop,
+                    previous => previous,
+                };
+
+                // Cache calendars and errors alike. Caching errors saves additional
+                // calls to upstream and parsing CPU time for paths that are often
+                // permanent fails anyways. Errors are cached for a shorter time, so
+                // temporary errors driven by upstream recover quickly.
+                let mut cached = CachedCalendar::new(fetch.await);
+
+                match previous {
+                    Some(mut previous)
+                        if cached.result.as_ref().is_err_and(|err| err.is_temporary())
+                            && previous.result.is_ok()
+                            && previous.age() < config.retention =>
+                    {
+                        previous.failed_refresh = Some(cached.timestamp);
+                        Op::Put(previous)
+                    }
+                    Some(previous) if previous.hash == cached.hash => {
+                        cached.last_modified = previous.last_modified;
+                        Op::Put(cached)
+                    }
+                    _ => Op::Put(cached),
+                }
+            })
+            .await;
+
+        if let (Some(store), CompResult::Inserted(entry) | CompResult::ReplacedWith(entry)) =
+            (&self.store, &result)
+        {
+            match entry.value().to_stored(entry.key()) {
+                Some(stored) => {
+                    if let Err(err) = store.save(&stored).await {
+                        tracing::error!(%err, "can't write cache entry to disk");
+                    }
+                }
+                None => store.remove(entry.key()),
+            }
+        }
+
+        result
+    }
+
+    /// Refreshes the key in a separate task, unless that is already happening.
+    fn refresh_in_background<F>(self: Arc<Self>, key: String, fetch: F)
+    where
+        F: Future<Output = Result<Calendar, Error>> + Send + 'static,
+    {
+        if !self.lock_refreshing().insert(key.clone()) {
+            return;
+        }
+
+        tokio::spawn(
+            async move {
+                self.refresh(key.clone(), fetch).await;
+                self.lock_refreshing().remove(&key);
+            }
+            .instrument(tracing::info_span!("refresh")),
+        );
+    }
+
+    fn lock_refreshing(&self) -> MutexGuard<'_, HashSet<String>> {
+        self.refreshing
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
 }
 
-async fn decompose_response(response: Response) -> CachedResponse {
-    let (parts, body) = response.into_parts();
-    let bytes = axum::body::to_bytes(body, usize::MAX)
+/// Adds an `ETag` to successful responses and answers conditional requests
+/// with `304 Not Modified`, based on the `ETag` and `Last-Modified` headers.
+pub fn apply_middleware(router: Router) -> Router {
+    router.route_layer(middleware::from_fn(conditional_middleware))
+}
+
+async fn conditional_middleware(request: Request, next: Next) -> Response {
+    let method = request.method().clone();
+    let headers = request.headers().clone();
+    let response = next.run(request).await;
+
+    if method != Method::GET || !response.status().is_success() {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let body = axum::body::to_bytes(body, usize::MAX)
         .await
         .expect("response size is bigger than max usize");
 
-    CachedResponse {
-        parts,
-        body: bytes,
-        timestamp: Instant::now(),
+    let etag = etag(&body);
+    parts.headers.insert(
+        header::ETAG,
+        etag.parse().expect("header value did not parse"),
+    );
+
+    if !is_not_modified(&headers, &etag, &parts.headers) {
+        return Response::from_parts(parts, Body::from(body));
+    }
+
+    // Keep the headers describing the response, but not the ones describing the body.
+    let mut response = StatusCode::NOT_MODIFIED.into_response();
+    for name in ["etag", "last-modified", "warning", CACHE_AGE_HEADER] {
+        if let Some(value) = parts.headers.remove(name) {
+            response.headers_mut().insert(name, value);
+        }
     }
+    *response.extensions_mut() = parts.extensions;
+
+    response
+}
+
+/// The entity tag of a response body.
+pub fn etag(body: &[u8]) -> String {
+    format!("\"{:016x}\"", xxh3_64(body))
 }
 
-pub fn apply_middleware(router: Router, (ttl, max_capacity): (Duration, u64)) -> Router {
-    let cache = Cache::builder()
-        .time_to_live(ttl)
-        .max_capacity(max_capacity * 1024 * 1024) // Megabytes, weigher measures bytes
-        .weigher(|url: &String, response: &CachedResponse| {
-            (mem::size_of::<CachedResponse>()
-                .saturating_add(url.len())
-                .saturating_add(response.body.len()))
-            .max(1)
-            .try_into()
-            .unwrap_or(u32::MAX)
+/// Whether a request with these headers already has the current response.
+fn is_not_modified(request: &HeaderMap, etag: &str, response: &HeaderMap) -> bool {
+    // If-None-Match takes precedence, If-Modified-Since is ignored when both are given.
+    if let Some(if_none_match) = request.get(header::IF_NONE_MATCH) {
+        return if_none_match.to_str().is_ok_and(|tags| {
+            tags.split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+        });
+    }
+
+    let http_date = |headers: &HeaderMap, name| {
+        httpdate::parse_http_date(headers.get(name)?.to_str().ok()?).ok()
+    };
+
+    match (
+        http_date(request, header::IF_MODIFIED_SINCE),
+        http_date(response, header::LAST_MODIFIED),
+    ) {
+        // HTTP dates have a resolution of one second.
+        (Some(since), Some(last_modified)) => unix_secs(last_modified) <= unix_secs(since),
+        _ => false,
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::sync::Notify;
+
+    use super::*;
+
+    const ETAG: &str = "\"0123456789abcdef\"";
+    const LAST_MODIFIED: &str = "Mon, 30 Sep 2024 08:00:00 GMT";
+
+    fn headers(pairs: &[(header::HeaderName, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.clone(), value.parse().unwrap()))
+            .collect()
+    }
+
+    fn not_modified(request: &[(header::HeaderName, &str)]) -> bool {
+        let response = headers(&[(header::LAST_MODIFIED, LAST_MODIFIED)]);
+        is_not_modified(&headers(request), ETAG, &response)
+    }
+
+    #[test]
+    fn matches_if_none_match() {
+        assert!(not_modified(&[(header::IF_NONE_MATCH, ETAG)]));
+        assert!(not_modified(&[(header::IF_NONE_MATCH, "*")]));
+        assert!(not_modified(&[(
+            header::IF_NONE_MATCH,
+            "W/\"0123456789abcdef\""
+        )]));
+        assert!(not_modified(&[(
+            header::IF_NONE_MATCH,
+            "\"other\", \"0123456789abcdef\""
+        )]));
+        assert!(!not_modified(&[(header::IF_NONE_MATCH, "\"other\"")]));
+        assert!(!not_modified(&[(
+            header::IF_NONE_MATCH,
+            "0123456789abcdef"
+        )]));
+    }
+
+    #[test]
+    fn compares_if_modified_since() {
+        assert!(not_modified(&[(header::IF_MODIFIED_SINCE, LAST_MODIFIED)]));
+        assert!(not_modified(&[(
+            header::IF_MODIFIED_SINCE,
+            "Tue, 01 Oct 2024 08:00:00 GMT"
+        )]));
+        assert!(!not_modified(&[(
+            header::IF_MODIFIED_SINCE,
+            "Mon, 30 Sep 2024 07:59:59 GMT"
+        )]));
+        assert!(!not_modified(&[(header::IF_MODIFIED_SINCE, "yesterday")]));
+        assert!(!not_modified(&[]));
+    }
+
+    #[test]
+    fn prefers_if_none_match() {
+        assert!(!not_modified(&[
+            (header::IF_NONE_MATCH, "\"other\""),
+            (header::IF_MODIFIED_SINCE, LAST_MODIFIED),
+        ]));
+        assert!(not_modified(&[
+            (header::IF_NONE_MATCH, ETAG),
+            (header::IF_MODIFIED_SINCE, "Mon, 30 Sep 2024 07:00:00 GMT"),
+        ]));
+    }
+
+    #[test]
+    fn needs_last_modified_for_if_modified_since() {
+        let request = headers(&[(header::IF_MODIFIED_SINCE, LAST_MODIFIED)]);
+        assert!(!is_not_modified(&request, ETAG, &HeaderMap::new()));
+    }
+
+    fn config(ttl: Duration) -> CacheConfig {
+        CacheConfig {
+            ttl,
+            error_ttl: Duration::from_secs(60),
+            retention: Duration::from_secs(3600),
+            max_capacity: 1,
+        }
+    }
+
+    async fn fetched(name: &str) -> Result<Calendar, Error> {
+        Ok(Calendar {
+            name: name.to_string(),
+            events: Vec::new(),
+            skipped: Vec::new(),
         })
-        .build();
-
-    router.route_layer(middleware::from_fn_with_state(
-        Arc::new(cache),
-        cache_middleware,
-    ))
-}
-
-async fn cache_middleware(
-    State(cache): State<Arc<Cache<String, CachedResponse>>>,
-    Extension(upstream): Extension<UpstreamUrlExtension>,
-    request: Request,
-    next: Next,
-) -> Response {
-    let mut cache_hit = true;
-
-    let cached = cache
-        .get_with(upstream.url, async {
-            cache_hit = false;
-            // Cache responses no matter their status. Caching errored responses
-            // saves additional calls to upstream and parsing CPU time for paths
-            // that are often permanent fails anyways. The only trade-off is
-            // that temporary errors driven by upstream will take the full time
-            // to live to recover from, even if upstream recovers earlier.
-            let response = next.run(request).await;
-            decompose_response(response).await
+    }
+
+    async fn failed() -> Result<Calendar, Error> {
+        Err(Error::CircuitOpen {
+            host: "rapla.dhbw.de".to_string(),
+            retry_after: Duration::from_secs(30),
         })
-        .await;
+    }
 
-    let mut response = Response::from_parts(cached.parts, Body::from(cached.body));
+    fn name(lookup: &CacheLookup) -> &str {
+        &lookup
+            .result
+            .as_ref()
+            .expect("calendar should be cached")
+            .name
+    }
 
-    if cache_hit {
-        let age = cached.timestamp.elapsed().as_secs().to_string();
-        response.headers_mut().insert(
-            CACHE_AGE_HEADER,
-            age.parse().expect("header value did not parse"),
-        );
+    async fn wait_for_refreshes(cache: &CalendarCache) {
+        while !cache.0.lock_refreshing().is_empty() {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
     }
 
-    response
+    #[tokio::test]
+    async fn serves_previous_calendar_when_refresh_fails() {
+        let cache = CalendarCache::new(config(Duration::ZERO), None).await;
+        cache.get("key".to_string(), fetched("first")).await;
+
+        let lookup = cache.get("key".to_string(), failed()).await;
+        assert_eq!(name(&lookup), "first");
+        assert_eq!(lookup.freshness.warning, Some(STALE_WARNING));
+        wait_for_refreshes(&cache).await;
+
+        // The failed refresh is cached for the error TTL, so this doesn't fetch.
+        let lookup = cache.get("key".to_string(), fetched("second")).await;
+        assert_eq!(name(&lookup), "first");
+
+        let mut response = Response::new(Body::empty());
+        lookup.freshness.apply_headers(&mut response);
+        assert_eq!(response.headers()[header::WARNING], FAILED_REFRESH_WARNING);
+    }
+
+    #[tokio::test]
+    async fn refreshes_expired_calendar_once() {
+        let cache = CalendarCache::new(config(Duration::ZERO), None).await;
+        cache.get("key".to_string(), fetched("first")).await;
+
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let release = Arc::new(Notify::new());
+        for _ in 0..3 {
+            let (fetches, release) = (fetches.clone(), release.clone());
+            let lookup = cache
+                .get("key".to_string(), async move {
+                    fetches.fetch_add(1, Ordering::SeqCst);
+                    release.notified().await;
+                    fetched("second").await
+                })
+                .await;
+
+            assert_eq!(name(&lookup), "first");
+            assert_eq!(lookup.freshness.warning, Some(STALE_WARNING));
+            tokio::task::yield_now().await;
+        }
+
+        release.notify_one();
+        wait_for_refreshes(&cache).await;
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+        let lookup = cache.get("key".to_string(), fetched("third")).await;
+        assert_eq!(name(&lookup), "second");
+    }
+
+    #[tokio::test]
+    async fn loads_calendars_from_disk() {
+        let dir = std::env::temp_dir().join(format!("rapla-cache-{}", std::process::id()));
+        let store = DiskStore::open(dir.clone()).unwrap();
+
+        let cache = CalendarCache::new(config(Duration::from_secs(60)), Some(store)).await;
+        let saved = cache.get("key".to_string(), fetched("first")).await;
+
+        let store = DiskStore::open(dir.clone()).unwrap();
+        let cache = CalendarCache::new(config(Duration::from_secs(60)), Some(store)).await;
+        let loaded = cache.get("key".to_string(), failed()).await;
+        let _ = std::fs::remove_dir_all(dir);
+
+        assert_eq!(name(&loaded), "first");
+        assert_eq!(
+            loaded.freshness.last_modified,
+            saved.freshness.last_modified
+        );
+        assert!(loaded.freshness.age.is_some());
+    }
 }
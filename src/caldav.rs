@@ -0,0 +1,752 @@
+use std::fmt::Write;
+use std::sync::Arc;
+
+use axum::Router;
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{HeaderMap, Method, StatusCode, Uri, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::any;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use html_escape::encode_text;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+use roxmltree::{Document, Node};
+
+use crate::cache::etag;
+use crate::calendar::{Calendar, Event};
+use crate::format::Format;
+use crate::proxy::Proxy;
+use crate::resolver::{
+    ResolveError, ResolverConfig, UpstreamUrlComponents, UpstreamUrlExtension, bad_request,
+};
+
+const DAV: &str = "DAV:";
+const CALDAV: &str = "urn:ietf:params:xml:ns:caldav";
+const CALENDARSERVER: &str = "http://calendarserver.org/ns/";
+
+const ALLOW: &str = "OPTIONS, GET, HEAD, PROPFIND, REPORT";
+const EVENT_CONTENT_TYPE: &str = "text/calendar; charset=utf-8; component=vevent";
+
+/// Characters of a UID that are kept as they are in resource names.
+const UID_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Serves every proxied calendar as a read-only CalDAV collection at
+/// `/caldav/<page>/<query>/`, where `<page>` and `<query>` are taken from the
+/// Rapla link, e.g. `/caldav/calendar/key=...&salt=.../`. Calendars on other
+/// hosts than the default one are at `/caldav/<host>/<page>/<query>/`.
+///
+/// Events are resources of the collection, named after their percent-encoded UID.
+pub fn apply_routes(router: Router, proxy: Proxy, config: Arc<ResolverConfig>) -> Router {
+    router.route(
+        "/caldav/{*path}",
+        any(caldav_handler).with_state((proxy, config)),
+    )
+}
+
+async fn caldav_handler(
+    State((proxy, config)): State<(Proxy, Arc<ResolverConfig>)>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    // Use the raw path, decoding it would break up encoded query parameters.
+    let path = uri.path().trim_start_matches("/caldav/");
+    let (host, path) = match path.split_once('/') {
+        Some((host, path)) if config.hosts.iter().any(|allowed| allowed == host) => {
+            (Some(host), path)
+        }
+        _ => (None, path),
+    };
+
+    let mut segments = path.splitn(3, '/');
+    let (Some(page), Some(query)) = (segments.next(), segments.next()) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let resource = segments.next().filter(|resource| !resource.is_empty());
+
+    let (rapla_url, href) = match host {
+        Some(host) => (
+            format!("https://{host}/rapla/{page}?{query}"),
+            format!("/caldav/{host}/{page}/{query}/"),
+        ),
+        None => (
+            format!("/rapla/{page}?{query}"),
+            format!("/caldav/{page}/{query}/"),
+        ),
+    };
+
+    let upstream = rapla_url
+        .parse::<Uri>()
+        .map_err(|_| ResolveError::NotRapla)
+        .and_then(|uri| UpstreamUrlComponents::from_request_uri(&uri, &config))
+        .and_then(|upstream| upstream.generate_url(&config));
+    let upstream = match upstream {
+        Ok(upstream) => upstream,
+        Err(err) => return err.to_response(&headers),
+    };
+
+    if method == Method::OPTIONS {
+        return (
+            [("dav", "1, calendar-access"), ("allow", ALLOW)],
+            StatusCode::OK,
+        )
+            .into_response();
+    }
+
+    let collection = Collection { href, upstream };
+
+    let lookup = proxy.fetch(&collection.upstream).await;
+    let mut response = match &lookup.result {
+        Ok(calendar) => {
+            let mut calendar = Calendar::clone(calendar);
+            collection.upstream.apply(&mut calendar);
+            match resource {
+                Some(resource) => collection.resource(&calendar, resource, &method, &body),
+                None => collection.collection(&calendar, &method, &uri, &headers, &body),
+            }
+        }
+        Err(err) => err.to_response(),
+    };
+
+    lookup.freshness.apply_headers(&mut response);
+    response
+}
+
+struct Collection {
+    /// Path of the collection, with a trailing slash.
+    href: String,
+    upstream: UpstreamUrlExtension,
+}
+
+/// An event as a standalone calendar object resource.
+struct EventResource {
+    href: String,
+    ics: String,
+    etag: String,
+}
+
+impl Collection {
+    fn collection(
+        &self,
+        calendar: &Calendar,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Response {
+        match method.as_str() {
+            "GET" | "HEAD" => {
+                Format::negotiate(self.upstream.format, headers).render(calendar, uri, headers)
+            }
+            "PROPFIND" => self.propfind(calendar, headers, body),
+            "REPORT" => self.report(calendar, body),
+            _ => method_not_allowed(),
+        }
+    }
+
+    fn resource(&self, calendar: &Calendar, name: &str, method: &Method, body: &[u8]) -> Response {
+        let Some(event) = self.find_event(calendar, name) else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+        let resource = self.event_resource(calendar, event);
+
+        match method.as_str() {
+            "GET" | "HEAD" => {
+                ([("content-type", EVENT_CONTENT_TYPE)], resource.ics).into_response()
+            }
+            "PROPFIND" => {
+                let Some(request) = parse_body(body) else {
+                    return bad_request("Could not parse request body");
+                };
+                let props = requested_props(&request, Target::Event);
+
+                let mut multistatus = Multistatus::new();
+                multistatus.response(&resource.href, &props, |prop| {
+                    event_property(prop, &resource)
+                });
+                multistatus.finish()
+            }
+            _ => method_not_allowed(),
+        }
+    }
+
+    fn propfind(&self, calendar: &Calendar, headers: &HeaderMap, body: &[u8]) -> Response {
+        let Some(request) = parse_body(body) else {
+            return bad_request("Could not parse request body");
+        };
+        let props = requested_props(&request, Target::Collection);
+        let member_props = requested_props(&request, Target::Event);
+        let ctag = ctag(calendar);
+
+        let mut multistatus = Multistatus::new();
+        multistatus.response(&self.href, &props, |prop| {
+            collection_property(prop, calendar, &ctag)
+        });
+
+        // Depth defaults to infinity, which is the same as 1 without nested collections.
+        let depth = headers.get("depth").and_then(|depth| depth.to_str().ok());
+        if depth != Some("0") {
+            for event in &calendar.events {
+                let resource = self.event_resource(calendar, event);
+                multistatus.response(&resource.href, &member_props, |prop| {
+                    event_property(prop, &resource)
+                });
+            }
+        }
+
+        multistatus.finish()
+    }
+
+    fn report(&self, calendar: &Calendar, body: &[u8]) -> Response {
+        let Some(request) = parse_body(body) else {
+            return bad_request("Could not parse request body");
+        };
+        let Some(document) = &request else {
+            return bad_request("Missing report in request body");
+        };
+
+        let root = document.root_element();
+        let props = requested_props(&request, Target::Event);
+        let mut multistatus = Multistatus::new();
+
+        match (root.tag_name().namespace(), root.tag_name().name()) {
+            (Some(CALDAV), "calendar-query") => {
+                // Queries for anything but events, e.g. tasks, have no results.
+                let filter = TimeRange::from_query(root);
+                let events = calendar
+                    .events
+                    .iter()
+                    .filter(|event| filter.as_ref().is_some_and(|filter| filter.matches(event)));
+                for event in events {
+                    let resource = self.event_resource(calendar, event);
+                    multistatus.response(&resource.href, &props, |prop| {
+                        event_property(prop, &resource)
+                    });
+                }
+            }
+            (Some(CALDAV), "calendar-multiget") => {
+                let hrefs = root
+                    .children()
+                    .filter(|node| is_element(node, DAV, "href"))
+                    .filter_map(|node| node.text());
+                for href in hrefs {
+                    let name = href.trim().rsplit('/').next().unwrap_or_default();
+                    match self.find_event(calendar, name) {
+                        Some(event) => {
+                            let resource = self.event_resource(calendar, event);
+                            multistatus.response(href.trim(), &props, |prop| {
+                                event_property(prop, &resource)
+                            });
+                        }
+                        None => multistatus.not_found(href.trim()),
+                    }
+                }
+            }
+            (Some(DAV), "sync-collection") => {
+                let token = sync_token(&ctag(calendar));
+                let client_token = root
+                    .children()
+                    .find(|node| is_element(node, DAV, "sync-token"))
+                    .and_then(|node| node.text())
+                    .map(str::trim)
+                    .unwrap_or_default();
+
+                // Without history, the only change set we can give is "everything".
+                // Clients fall back to a full sync when their token is rejected.
+                if client_token.is_empty() {
+                    for event in &calendar.events {
+                        let resource = self.event_resource(calendar, event);
+                        multistatus.response(&resource.href, &props, |prop| {
+                            event_property(prop, &resource)
+                        });
+                    }
+                } else if client_token != token {
+                    return invalid_sync_token();
+                }
+
+                multistatus.sync_token(&token);
+            }
+            _ => return bad_request("Unsupported report"),
+        }
+
+        multistatus.finish()
+    }
+
+    /// Finds the event for a resource name taken from a path, which is still percent-encoded.
+    fn find_event<'a>(&self, calendar: &'a Calendar, name: &str) -> Option<&'a Event> {
+        let uid = percent_decode_str(name.strip_suffix(".ics")?)
+            .decode_utf8()
+            .ok()?;
+        calendar.events.iter().find(|event| event.uid == uid)
+    }
+
+    fn event_resource(&self, calendar: &Calendar, event: &Event) -> EventResource {
+        let ics = Calendar {
+            name: calendar.name.clone(),
+            events: vec![event.clone()],
+            skipped: Vec::new(),
+        }
+        .to_ics()
+        .to_string();
+
+        EventResource {
+            href: format!(
+                "{}{}.ics",
+                self.href,
+                utf8_percent_encode(&event.uid, UID_SET)
+            ),
+            etag: etag(ics.as_bytes()),
+            ics,
+        }
+    }
+}
+
+/// Changes whenever the rendered calendar does.
+fn ctag(calendar: &Calendar) -> String {
+    etag(calendar.to_ics().to_string().as_bytes())
+        .trim_matches('"')
+        .to_string()
+}
+
+fn sync_token(ctag: &str) -> String {
+    format!("urn:rapla-ical-proxy:sync:{ctag}")
+}
+
+/// Properties of the collection, as XML content.
+fn collection_property(prop: &PropName, calendar: &Calendar, ctag: &str) -> Option<String> {
+    let value = match (prop.namespace.as_str(), prop.name.as_str()) {
+        (DAV, "resourcetype") => "<d:collection/><c:calendar/>".into(),
+        (DAV, "displayname") => encode_text(&calendar.name).into_owned(),
+        (DAV, "sync-token") => sync_token(ctag),
+        (DAV, "current-user-privilege-set") => "<d:privilege><d:read/></d:privilege>".into(),
+        (DAV, "supported-report-set") => concat!(
+            "<d:supported-report><d:report><c:calendar-query/></d:report></d:supported-report>",
+            "<d:supported-report><d:report><c:calendar-multiget/></d:report></d:supported-report>",
+            "<d:supported-report><d:report><d:sync-collection/></d:report></d:supported-report>",
+        )
+        .into(),
+        (CALDAV, "supported-calendar-component-set") => r#"<c:comp name="VEVENT"/>"#.into(),
+        (CALENDARSERVER, "getctag") => ctag.into(),
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Properties of an event resource, as XML content.
+fn event_property(prop: &PropName, resource: &EventResource) -> Option<String> {
+    let value = match (prop.namespace.as_str(), prop.name.as_str()) {
+        (DAV, "resourcetype") => String::new(),
+        (DAV, "getetag") => encode_text(&resource.etag).into_owned(),
+        (DAV, "getcontenttype") => EVENT_CONTENT_TYPE.into(),
+        (CALDAV, "calendar-data") => encode_text(&resource.ics).into_owned(),
+        _ => return None,
+    };
+    Some(value)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PropName {
+    namespace: String,
+    name: String,
+}
+
+impl PropName {
+    fn new(namespace: &str, name: &str) -> Self {
+        Self {
+            namespace: namespace.into(),
+            name: name.into(),
+        }
+    }
+
+    fn write(&self, xml: &mut String, content: Option<&str>) {
+        let prefix = match self.namespace.as_str() {
+            DAV => "d",
+            CALDAV => "c",
+            CALENDARSERVER => "cs",
+            _ => "x",
+        };
+        let namespace = if prefix == "x" {
+            format!(r#" xmlns:x="{}""#, encode_text(&self.namespace))
+        } else {
+            String::new()
+        };
+
+        let _ = match content {
+            Some(content) => write!(
+                xml,
+                "<{prefix}:{name}{namespace}>{content}</{prefix}:{name}>",
+                name = self.name
+            ),
+            None => write!(xml, "<{prefix}:{name}{namespace}/>", name = self.name),
+        };
+    }
+}
+
+/// Which properties `allprop` stands for.
+#[derive(Clone, Copy)]
+enum Target {
+    Collection,
+    Event,
+}
+
+/// Parses the request body, `None` if it isn't XML and `Some(None)` if it is empty.
+fn parse_body(body: &[u8]) -> Option<Option<Document<'_>>> {
+    let body = std::str::from_utf8(body).ok()?;
+    if body.trim().is_empty() {
+        return Some(None);
+    }
+    Document::parse(body).ok().map(Some)
+}
+
+/// The properties listed in the request's `prop` element, or all properties
+/// we know for `allprop` requests and empty bodies.
+fn requested_props(request: &Option<Document<'_>>, target: Target) -> Vec<PropName> {
+    let prop = request.as_ref().and_then(|document| {
+        document
+            .root_element()
+            .children()
+            .find(|node| is_element(node, DAV, "prop"))
+    });
+
+    if let Some(prop) = prop {
+        return prop
+            .children()
+            .filter(Node::is_element)
+            .map(|node| {
+                let name = node.tag_name();
+                PropName::new(name.namespace().unwrap_or_default(), name.name())
+            })
+            .collect();
+    }
+
+    match target {
+        Target::Collection => vec![
+            PropName::new(DAV, "resourcetype"),
+            PropName::new(DAV, "displayname"),
+            PropName::new(DAV, "sync-token"),
+            PropName::new(CALDAV, "supported-calendar-component-set"),
+            PropName::new(CALENDARSERVER, "getctag"),
+        ],
+        Target::Event => vec![
+            PropName::new(DAV, "resourcetype"),
+            PropName::new(DAV, "getetag"),
+            PropName::new(DAV, "getcontenttype"),
+        ],
+    }
+}
+
+fn is_element(node: &Node<'_, '_>, namespace: &str, name: &str) -> bool {
+    node.is_element()
+        && node.tag_name().namespace() == Some(namespace)
+        && node.tag_name().name() == name
+}
+
+/// The time range of a calendar-query, the only filter we support.
+struct TimeRange {
+    start: Option<DateTime<Utc>>,
+    end: Option<DateTime<Utc>>,
+}
+
+impl TimeRange {
+    /// Returns `None` if the query filters for anything but events, or is invalid.
+    fn from_query(query: Node<'_, '_>) -> Option<Self> {
+        let mut range = Self {
+            start: None,
+            end: None,
+        };
+
+        for node in query.descendants() {
+            if is_element(&node, CALDAV, "comp-filter")
+                && !matches!(node.attribute("name"), Some("VCALENDAR" | "VEVENT"))
+            {
+                return None;
+            }
+
+            if is_element(&node, CALDAV, "time-range") {
+                let parse = |name| {
+                    node.attribute(name)
+                        .map(|time| NaiveDateTime::parse_from_str(time, "%Y%m%dT%H%M%SZ"))
+                        .transpose()
+                        .map(|time| time.map(|time| time.and_utc()))
+                };
+                range.start = parse("start").ok()?;
+                range.end = parse("end").ok()?;
+            }
+        }
+
+        Some(range)
+    }
+
+    fn matches(&self, event: &Event) -> bool {
+        self.start.is_none_or(|start| event.ends_at() > start)
+            && self.end.is_none_or(|end| event.starts_at() < end)
+    }
+}
+
+/// Builds a `207 Multi-Status` response.
+struct Multistatus(String);
+
+impl Multistatus {
+    fn new() -> Self {
+        Self(format!(
+            r#"<?xml version="1.0" encoding="utf-8"?><d:multistatus xmlns:d="{DAV}" xmlns:c="{CALDAV}" xmlns:cs="{CALENDARSERVER}">"#
+        ))
+    }
+
+    fn response(
+        &mut self,
+        href: &str,
+        props: &[PropName],
+        mut property: impl FnMut(&PropName) -> Option<String>,
+    ) {
+        let (mut found, mut missing) = (String::new(), String::new());
+        for prop in props {
+            match property(prop) {
+                Some(value) => prop.write(&mut found, Some(&value)),
+                None => prop.write(&mut missing, None),
+            }
+        }
+
+        let xml = &mut self.0;
+        let _ = write!(xml, "<d:response><d:href>{}</d:href>", encode_text(href));
+        for (props, status) in [(found, "200 OK"), (missing, "404 Not Found")] {
+            if !props.is_empty() {
+                let _ = write!(
+                    xml,
+                    "<d:propstat><d:prop>{props}</d:prop><d:status>HTTP/1.1 {status}</d:status></d:propstat>"
+                );
+            }
+        }
+        xml.push_str("</d:response>");
+    }
+
+    fn not_found(&mut self, href: &str) {
+        let _ = write!(
+            self.0,
+            "<d:response><d:href>{}</d:href><d:status>HTTP/1.1 404 Not Found</d:status></d:response>",
+            encode_text(href)
+        );
+    }
+
+    fn sync_token(&mut self, token: &str) {
+        let _ = write!(
+            self.0,
+            "<d:sync-token>{}</d:sync-token>",
+            encode_text(token)
+        );
+    }
+
+    fn finish(mut self) -> Response {
+        self.0.push_str("</d:multistatus>");
+        (
+            StatusCode::MULTI_STATUS,
+            [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+            self.0,
+        )
+            .into_response()
+    }
+}
+
+fn invalid_sync_token() -> Response {
+    (
+        StatusCode::FORBIDDEN,
+        [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+        format!(
+            r#"<?xml version="1.0" encoding="utf-8"?><d:error xmlns:d="{DAV}"><d:valid-sync-token/></d:error>"#
+        ),
+    )
+        .into_response()
+}
+
+fn method_not_allowed() -> Response {
+    (StatusCode::METHOD_NOT_ALLOWED, [("allow", ALLOW)]).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::to_bytes;
+    use chrono::{NaiveDate, NaiveTime};
+
+    use super::*;
+
+    const HREF: &str = "/caldav/calendar/key=a&salt=b/";
+
+    fn event(uid: &str, date: NaiveDate) -> Event {
+        Event {
+            uid: uid.into(),
+            date,
+            start: NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(10, 0, 0).unwrap(),
+            title: "Mathe".into(),
+            location: None,
+            organizer: None,
+            description: None,
+            kind: None,
+            course: None,
+            remarks: None,
+            url: None,
+        }
+    }
+
+    fn calendar() -> Calendar {
+        Calendar {
+            name: "TINF22B".into(),
+            events: vec![
+                event("rapla-abc", NaiveDate::from_ymd_opt(2024, 9, 30).unwrap()),
+                event("rapla/x y", NaiveDate::from_ymd_opt(2024, 12, 2).unwrap()),
+            ],
+            skipped: Vec::new(),
+        }
+    }
+
+    fn collection() -> Collection {
+        let uri = "/rapla/calendar?key=a&salt=b".parse().unwrap();
+        let config = ResolverConfig::default();
+        Collection {
+            href: HREF.into(),
+            upstream: UpstreamUrlComponents::from_request_uri(&uri, &config)
+                .unwrap()
+                .generate_url(&config)
+                .unwrap(),
+        }
+    }
+
+    fn method(name: &str) -> Method {
+        Method::from_bytes(name.as_bytes()).unwrap()
+    }
+
+    /// The href and first status of every response in a multistatus body.
+    async fn responses(response: Response) -> Vec<(String, String)> {
+        assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let document = Document::parse(&body).unwrap();
+
+        let text = |node: Node<'_, '_>, name| {
+            node.descendants()
+                .find(|node| is_element(node, DAV, name))
+                .and_then(|node| node.text())
+                .unwrap_or_default()
+                .to_string()
+        };
+        document
+            .root_element()
+            .children()
+            .filter(|node| is_element(node, DAV, "response"))
+            .map(|node| (text(node, "href"), text(node, "status")))
+            .collect()
+    }
+
+    fn hrefs(responses: &[(String, String)]) -> Vec<&str> {
+        responses.iter().map(|(href, _)| href.as_str()).collect()
+    }
+
+    async fn propfind(depth: &str) -> Vec<(String, String)> {
+        let mut headers = HeaderMap::new();
+        headers.insert("depth", depth.parse().unwrap());
+        let uri = HREF.parse().unwrap();
+        let response =
+            collection().collection(&calendar(), &method("PROPFIND"), &uri, &headers, b"");
+        responses(response).await
+    }
+
+    async fn report(body: &str) -> Vec<(String, String)> {
+        let uri = HREF.parse().unwrap();
+        let response = collection().collection(
+            &calendar(),
+            &method("REPORT"),
+            &uri,
+            &HeaderMap::new(),
+            body.as_bytes(),
+        );
+        responses(response).await
+    }
+
+    #[tokio::test]
+    async fn lists_collection_at_depth_zero() {
+        assert_eq!(hrefs(&propfind("0").await), [HREF]);
+    }
+
+    #[tokio::test]
+    async fn lists_events_at_depth_one() {
+        let responses = propfind("1").await;
+        assert_eq!(
+            hrefs(&responses),
+            [
+                HREF,
+                "/caldav/calendar/key=a&salt=b/rapla-abc.ics",
+                "/caldav/calendar/key=a&salt=b/rapla%2Fx%20y.ics",
+            ]
+        );
+        assert!(
+            responses
+                .iter()
+                .all(|(_, status)| status == "HTTP/1.1 200 OK")
+        );
+    }
+
+    #[tokio::test]
+    async fn queries_time_range() {
+        let responses = report(
+            r#"<c:calendar-query xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+                <d:prop><d:getetag/></d:prop>
+                <c:filter>
+                    <c:comp-filter name="VCALENDAR">
+                        <c:comp-filter name="VEVENT">
+                            <c:time-range start="20241101T000000Z" end="20250101T000000Z"/>
+                        </c:comp-filter>
+                    </c:comp-filter>
+                </c:filter>
+            </c:calendar-query>"#,
+        )
+        .await;
+        assert_eq!(
+            hrefs(&responses),
+            ["/caldav/calendar/key=a&salt=b/rapla%2Fx%20y.ics"]
+        );
+    }
+
+    #[tokio::test]
+    async fn gets_multiple_events() {
+        let responses = report(
+            r#"<c:calendar-multiget xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+                <d:prop><d:getetag/><c:calendar-data/></d:prop>
+                <d:href>/caldav/calendar/key=a&amp;salt=b/rapla%2Fx%20y.ics</d:href>
+                <d:href>/caldav/calendar/key=a&amp;salt=b/missing.ics</d:href>
+            </c:calendar-multiget>"#,
+        )
+        .await;
+        assert_eq!(
+            responses,
+            [
+                (
+                    "/caldav/calendar/key=a&salt=b/rapla%2Fx%20y.ics".to_string(),
+                    "HTTP/1.1 200 OK".to_string()
+                ),
+                (
+                    "/caldav/calendar/key=a&salt=b/missing.ics".to_string(),
+                    "HTTP/1.1 404 Not Found".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_event_by_encoded_name() {
+        let calendar = calendar();
+        let collection = collection();
+        let found = |name| {
+            collection
+                .find_event(&calendar, name)
+                .map(|event| &event.uid)
+        };
+        assert_eq!(found("rapla%2Fx%20y.ics").unwrap(), "rapla/x y");
+        assert_eq!(found("rapla-abc.ics").unwrap(), "rapla-abc");
+        assert_eq!(found("rapla-abc"), None);
+    }
+}
@@ -1,16 +1,89 @@
-use chrono::{NaiveDate, NaiveTime};
+use std::borrow::Cow;
+use std::fmt::Write;
+
+use chrono::{DateTime, Datelike, Days, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use html_escape::encode_text;
+use ics::components::Property as IcsProperty;
+use ics::escape_text;
 use ics::parameters::TzIDParam;
-use ics::properties::{Description, DtEnd, DtStart, Location, Organizer, RRule, Summary, TzName};
+use ics::properties::{
+    Categories, Description, DtEnd, DtStart, Location, Organizer, RRule, Summary, TzName, URL,
+};
 use ics::{Daylight, Standard, TimeZone};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::parser::ParseError;
+
+const TZID: &str = "Europe/Berlin";
+
+/// A yearly switch between standard and daylight saving time, on the last
+/// Sunday of `month`.
+struct Transition {
+    kind: &'static str,
+    start: &'static str,
+    offset_from: &'static str,
+    offset_to: &'static str,
+    name: &'static str,
+    month: u32,
+}
+
+const TRANSITIONS: [Transition; 2] = [
+    Transition {
+        kind: "daylight",
+        start: "19700329T020000",
+        offset_from: "+0100",
+        offset_to: "+0200",
+        name: "CEST",
+        month: 3,
+    },
+    Transition {
+        kind: "standard",
+        start: "19701025T030000",
+        offset_from: "+0200",
+        offset_to: "+0100",
+        name: "CET",
+        month: 10,
+    },
+];
 
-#[derive(Debug)]
+impl Transition {
+    fn rrule(&self) -> String {
+        format!("FREQ=YEARLY;BYMONTH={};BYDAY=-1SU", self.month)
+    }
+
+    fn to_component(&self) -> Component<'static> {
+        let start = NaiveDateTime::parse_from_str(self.start, ICS_DATE_TIME)
+            .expect("transition start should be valid");
+
+        Component {
+            name: self.kind,
+            properties: vec![
+                Property::new("dtstart", Value::DateTime(start)),
+                Property::new("tzoffsetfrom", Value::UtcOffset(self.offset_from)),
+                Property::new("tzoffsetto", Value::UtcOffset(self.offset_to)),
+                Property::new("tzname", Value::Text(self.name.into())),
+                Property::new("rrule", Value::Recur { month: self.month }),
+            ],
+            components: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Calendar {
     pub name: String,
     pub events: Vec<Event>,
+    /// Pages, weeks and events that were left out because they couldn't be parsed.
+    /// Only kept in memory, they are meant for diagnostics of the current parse.
+    #[serde(skip)]
+    pub skipped: Vec<ParseError>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Event {
+    /// Stays the same when the event is moved within its day or renamed.
+    pub uid: String,
     pub date: NaiveDate,
     pub start: NaiveTime,
     pub end: NaiveTime,
@@ -18,23 +91,59 @@ pub struct Event {
     pub location: Option<String>,
     pub organizer: Option<String>,
     pub description: Option<String>,
+    /// Rapla's reservation type, e.g. "Vorlesung" or "Klausur".
+    pub kind: Option<String>,
+    /// The courses the event is for, as listed in Rapla's tooltip.
+    pub course: Option<String>,
+    pub remarks: Option<String>,
+    /// Link to the reservation on the Rapla site.
+    pub url: Option<String>,
+}
+
+/// The JSON representation of a calendar, with absolute times instead of
+/// the date and local times kept internally.
+#[derive(Debug, Serialize)]
+pub struct JsonCalendar<'a> {
+    pub name: &'a str,
+    pub events: Vec<JsonEvent<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonEvent<'a> {
+    pub uid: &'a str,
+    pub start: DateTime<FixedOffset>,
+    pub end: DateTime<FixedOffset>,
+    pub title: &'a str,
+    pub location: Option<&'a str>,
+    pub organizer: Option<&'a str>,
+    pub description: Option<&'a str>,
+    pub kind: Option<&'a str>,
+    pub course: Option<&'a str>,
+    pub remarks: Option<&'a str>,
+    pub url: Option<&'a str>,
 }
 
 impl Calendar {
     #[must_use]
     pub fn to_ics(&self) -> ics::ICalendar<'_> {
-        let mut cet_standard = Standard::new("19701025T030000", "+0200", "+0100");
-        cet_standard.push(TzName::new("CET"));
-        cet_standard.push(RRule::new("FREQ=YEARLY;BYMONTH=10;BYDAY=-1SU"));
+        let [daylight, standard] = &TRANSITIONS;
+
+        let mut cest_daylight =
+            Daylight::new(daylight.start, daylight.offset_from, daylight.offset_to);
+        cest_daylight.push(TzName::new(daylight.name));
+        cest_daylight.push(RRule::new(daylight.rrule()));
 
-        let mut cest_daylight = Daylight::new("19700329T020000", "+0100", "+0200");
-        cest_daylight.push(TzName::new("CEST"));
-        cest_daylight.push(RRule::new("FREQ=YEARLY;BYMONTH=3;BYDAY=-1SU"));
+        let mut cet_standard =
+            Standard::new(standard.start, standard.offset_from, standard.offset_to);
+        cet_standard.push(TzName::new(standard.name));
+        cet_standard.push(RRule::new(standard.rrule()));
 
-        let mut timezone = TimeZone::daylight("Europe/Berlin", cest_daylight);
+        let mut timezone = TimeZone::daylight(TZID, cest_daylight);
         timezone.add_standard(cet_standard);
 
-        let mut icalendar = ics::ICalendar::new("2.0", &self.name);
+        let mut icalendar = ics::ICalendar::new("2.0", escape_text(&self.name));
+        // PRODID isn't meant for display, this is the name calendar apps show.
+        icalendar.push(IcsProperty::new("X-WR-CALNAME", escape_text(&self.name)));
         icalendar.add_timezone(timezone);
 
         for event in &self.events {
@@ -43,6 +152,53 @@ impl Calendar {
 
         icalendar
     }
+
+    /// Renders the calendar as jCal (RFC 7265).
+    #[must_use]
+    pub fn to_jcal(&self) -> serde_json::Value {
+        self.to_component().to_jcal()
+    }
+
+    /// Renders the calendar as xCal (RFC 6321).
+    #[must_use]
+    pub fn to_xcal(&self) -> String {
+        let mut xml = String::from(concat!(
+            r#"<?xml version="1.0" encoding="utf-8"?>"#,
+            r#"<icalendar xmlns="urn:ietf:params:xml:ns:icalendar-2.0">"#,
+        ));
+        self.to_component().write_xcal(&mut xml);
+        xml.push_str("</icalendar>");
+        xml
+    }
+
+    /// The same structure as [`Calendar::to_ics`], for the other formats.
+    fn to_component(&self) -> Component<'_> {
+        let timezone = Component {
+            name: "vtimezone",
+            properties: vec![Property::new("tzid", Value::Text(TZID.into()))],
+            components: TRANSITIONS.iter().map(Transition::to_component).collect(),
+        };
+
+        Component {
+            name: "vcalendar",
+            properties: vec![
+                Property::new("version", Value::Text("2.0".into())),
+                Property::new("prodid", Value::Text(self.name.as_str().into())),
+                Property::new("x-wr-calname", Value::Text(self.name.as_str().into())),
+            ],
+            components: std::iter::once(timezone)
+                .chain(self.events.iter().map(Event::to_component))
+                .collect(),
+        }
+    }
+
+    #[must_use]
+    pub fn to_json(&self) -> JsonCalendar<'_> {
+        JsonCalendar {
+            name: &self.name,
+            events: self.events.iter().map(Event::to_json).collect(),
+        }
+    }
 }
 
 impl Event {
@@ -60,32 +216,587 @@ impl Event {
             self.end.format("%H%M")
         );
 
-        let id = format!("{}_{}", start, self.title.replace(' ', "-"));
-
-        let mut ics_event = ics::Event::new(id, start.clone());
+        let stamp = self.stamp().format(ICS_UTC_DATE_TIME).to_string();
+        let mut ics_event = ics::Event::new(&self.uid, stamp);
 
         let mut dtstart = DtStart::new(start);
-        dtstart.add(TzIDParam::new("Europe/Berlin"));
+        dtstart.add(TzIDParam::new(TZID));
 
         let mut dtend = DtEnd::new(end);
-        dtend.add(TzIDParam::new("Europe/Berlin"));
+        dtend.add(TzIDParam::new(TZID));
 
         ics_event.push(dtstart);
         ics_event.push(dtend);
-        ics_event.push(Summary::new(&self.title));
+        ics_event.push(Summary::new(escape_text(&self.title)));
 
         if let Some(location) = &self.location {
-            ics_event.push(Location::new(location));
+            ics_event.push(Location::new(escape_text(location)));
         }
 
         if let Some(organizer) = &self.organizer {
             ics_event.push(Organizer::new(organizer));
         }
 
-        if let Some(description) = &self.description {
-            ics_event.push(Description::new(description));
+        if let Some(description) = self.full_description() {
+            ics_event.push(Description::new(escape_text(description)));
+        }
+
+        if let Some(kind) = &self.kind {
+            ics_event.push(Categories::new(escape_text(kind)));
+        }
+
+        if let Some(course) = &self.course {
+            ics_event.push(Categories::new(escape_text(course)));
+        }
+
+        if let Some(url) = &self.url {
+            ics_event.push(URL::new(url));
         }
 
         ics_event
     }
+
+    #[must_use]
+    pub fn starts_at(&self) -> DateTime<FixedOffset> {
+        berlin_time(self.date.and_time(self.start))
+    }
+
+    #[must_use]
+    pub fn ends_at(&self) -> DateTime<FixedOffset> {
+        berlin_time(self.date.and_time(self.end))
+    }
+
+    /// The DTSTAMP of the event. Rapla doesn't tell when an event was changed,
+    /// so this is its start in UTC, which keeps renderings of an unchanged
+    /// calendar identical.
+    fn stamp(&self) -> DateTime<Utc> {
+        self.starts_at().with_timezone(&Utc)
+    }
+
+    /// Remarks and description, as they end up in the rendered description.
+    fn full_description(&self) -> Option<String> {
+        let description = [&self.remarks, &self.description]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        (!description.is_empty()).then(|| description.join("\n\n"))
+    }
+
+    fn to_component(&self) -> Component<'_> {
+        let start = self.date.and_time(self.start);
+        let end = self.date.and_time(self.end);
+
+        let mut properties = vec![
+            Property::new("uid", Value::Text(self.uid.as_str().into())),
+            Property::new("dtstamp", Value::UtcDateTime(self.stamp())),
+            Property::new("dtstart", Value::DateTime(start)).with_tzid(),
+            Property::new("dtend", Value::DateTime(end)).with_tzid(),
+            Property::new("summary", Value::Text(self.title.as_str().into())),
+        ];
+
+        if let Some(location) = &self.location {
+            properties.push(Property::new("location", Value::Text(location.into())));
+        }
+
+        if let Some(organizer) = &self.organizer {
+            properties.push(Property::new("organizer", Value::CalAddress(organizer)));
+        }
+
+        if let Some(description) = self.full_description() {
+            properties.push(Property::new(
+                "description",
+                Value::Text(description.into()),
+            ));
+        }
+
+        if let Some(kind) = &self.kind {
+            properties.push(Property::new("categories", Value::Text(kind.into())));
+        }
+
+        if let Some(course) = &self.course {
+            properties.push(Property::new("categories", Value::Text(course.into())));
+        }
+
+        if let Some(url) = &self.url {
+            properties.push(Property::new("url", Value::Uri(url)));
+        }
+
+        Component {
+            name: "vevent",
+            properties,
+            components: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn to_json(&self) -> JsonEvent<'_> {
+        JsonEvent {
+            uid: &self.uid,
+            start: self.starts_at(),
+            end: self.ends_at(),
+            title: &self.title,
+            location: self.location.as_deref(),
+            organizer: self.organizer.as_deref(),
+            description: self.description.as_deref(),
+            kind: self.kind.as_deref(),
+            course: self.course.as_deref(),
+            remarks: self.remarks.as_deref(),
+            url: self.url.as_deref(),
+        }
+    }
+}
+
+/// Attaches the Europe/Berlin offset, following the same rules as the
+/// VTIMEZONE in [`Calendar::to_ics`]. Times skipped or repeated by a
+/// transition are taken as summer time.
+fn berlin_time(datetime: NaiveDateTime) -> DateTime<FixedOffset> {
+    fn last_sunday(year: i32, month: u32) -> NaiveDate {
+        let last_day = NaiveDate::from_ymd_opt(year, month + 1, 1)
+            .and_then(|first_day| first_day.pred_opt())
+            .expect("month should be valid");
+        last_day - Days::new(last_day.weekday().num_days_from_sunday().into())
+    }
+
+    let year = datetime.year();
+    let summer_start = last_sunday(year, 3).and_hms_opt(2, 0, 0).unwrap();
+    let summer_end = last_sunday(year, 10).and_hms_opt(3, 0, 0).unwrap();
+
+    let hours = if (summer_start..summer_end).contains(&datetime) {
+        2
+    } else {
+        1
+    };
+
+    let offset = FixedOffset::east_opt(hours * 3600).expect("offset should be in range");
+    datetime
+        .and_local_timezone(offset)
+        .single()
+        .expect("fixed offsets are unambiguous")
+}
+
+const ICS_DATE_TIME: &str = "%Y%m%dT%H%M%S";
+const ICS_UTC_DATE_TIME: &str = "%Y%m%dT%H%M%SZ";
+
+/// An iCalendar component in the shape shared by jCal and xCal, which both
+/// map components, properties and typed values one to one.
+struct Component<'a> {
+    name: &'static str,
+    properties: Vec<Property<'a>>,
+    components: Vec<Component<'a>>,
+}
+
+struct Property<'a> {
+    name: &'static str,
+    /// Whether the value is local time in [`TZID`].
+    tzid: bool,
+    value: Value<'a>,
+}
+
+enum Value<'a> {
+    Text(Cow<'a, str>),
+    DateTime(NaiveDateTime),
+    UtcDateTime(DateTime<Utc>),
+    /// In the basic ICS format, e.g. `+0100`.
+    UtcOffset(&'static str),
+    /// A yearly recurrence on the last Sunday of `month`.
+    Recur {
+        month: u32,
+    },
+    CalAddress(&'a str),
+    Uri(&'a str),
+}
+
+impl<'a> Property<'a> {
+    fn new(name: &'static str, value: Value<'a>) -> Self {
+        Self {
+            name,
+            tzid: false,
+            value,
+        }
+    }
+
+    fn with_tzid(self) -> Self {
+        Self { tzid: true, ..self }
+    }
+}
+
+impl Value<'_> {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Self::Text(_) => "text",
+            Self::DateTime(_) | Self::UtcDateTime(_) => "date-time",
+            Self::UtcOffset(_) => "utc-offset",
+            Self::Recur { .. } => "recur",
+            Self::CalAddress(_) => "cal-address",
+            Self::Uri(_) => "uri",
+        }
+    }
+
+    /// The value as text, in the extended formats jCal and xCal use.
+    fn to_text(&self) -> Cow<'_, str> {
+        match self {
+            Self::Text(text) => Cow::Borrowed(text),
+            Self::DateTime(datetime) => datetime.format("%Y-%m-%dT%H:%M:%S").to_string().into(),
+            Self::UtcDateTime(datetime) => datetime.format("%Y-%m-%dT%H:%M:%SZ").to_string().into(),
+            Self::UtcOffset(offset) => format!("{}:{}", &offset[..3], &offset[3..]).into(),
+            Self::Recur { .. } => unreachable!("recurrences are structured"),
+            Self::CalAddress(text) | Self::Uri(text) => Cow::Borrowed(text),
+        }
+    }
+}
+
+impl Component<'_> {
+    fn to_jcal(&self) -> serde_json::Value {
+        let properties = self.properties.iter().map(|property| {
+            let parameters = if property.tzid {
+                json!({ "tzid": TZID })
+            } else {
+                json!({})
+            };
+
+            let value = match property.value {
+                Value::Recur { month } => json!({
+                    "freq": "YEARLY",
+                    "bymonth": month,
+                    "byday": "-1SU",
+                }),
+                ref value => json!(value.to_text()),
+            };
+
+            json!([property.name, parameters, property.value.type_name(), value])
+        });
+
+        json!([
+            self.name,
+            properties.collect::<Vec<_>>(),
+            self.components
+                .iter()
+                .map(Component::to_jcal)
+                .collect::<Vec<_>>(),
+        ])
+    }
+
+    fn write_xcal(&self, xml: &mut String) {
+        let _ = write!(xml, "<{}><properties>", self.name);
+        for property in &self.properties {
+            let _ = write!(xml, "<{}>", property.name);
+            if property.tzid {
+                let _ = write!(
+                    xml,
+                    "<parameters><tzid><text>{TZID}</text></tzid></parameters>"
+                );
+            }
+
+            let value_type = property.value.type_name();
+            match property.value {
+                Value::Recur { month } => {
+                    let _ = write!(
+                        xml,
+                        "<{value_type}><freq>YEARLY</freq><bymonth>{month}</bymonth><byday>-1SU</byday></{value_type}>"
+                    );
+                }
+                ref value => {
+                    let text = encode_text(&value.to_text()).into_owned();
+                    let _ = write!(xml, "<{value_type}>{text}</{value_type}>");
+                }
+            }
+            let _ = write!(xml, "</{}>", property.name);
+        }
+        xml.push_str("</properties>");
+
+        if !self.components.is_empty() {
+            xml.push_str("<components>");
+            for component in &self.components {
+                component.write_xcal(xml);
+            }
+            xml.push_str("</components>");
+        }
+        let _ = write!(xml, "</{}>", self.name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value as Json;
+
+    use super::*;
+
+    /// A component in a shape all three formats can be compared in, with
+    /// values as they appear in ICS.
+    #[derive(Debug, Default, PartialEq)]
+    struct Tree {
+        name: String,
+        /// Name, TZID parameter and value, sorted as the order doesn't matter.
+        properties: Vec<(String, Option<String>, String)>,
+        components: Vec<Tree>,
+    }
+
+    impl Tree {
+        fn sorted(mut self) -> Self {
+            self.properties.sort();
+            self.components = self.components.into_iter().map(Tree::sorted).collect();
+            self
+        }
+    }
+
+    fn calendar() -> Calendar {
+        let date = NaiveDate::from_ymd_opt(2024, 9, 30).unwrap();
+        Calendar {
+            name: "TINF22B, Kurs A".into(),
+            events: vec![
+                Event {
+                    uid: "rapla-abc-20240930".into(),
+                    date,
+                    start: NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+                    end: NaiveTime::from_hms_opt(10, 15, 0).unwrap(),
+                    title: "Mathe, Analysis; Teil 1 <Übung>".into(),
+                    location: Some(r"A1\B".into()),
+                    organizer: Some("Müller".into()),
+                    description: Some("A1, A2".into()),
+                    kind: Some("Vorlesung".into()),
+                    course: Some("TINF22B1, TINF22B2".into()),
+                    remarks: Some("Bitte Laptop mitbringen\nund & Skript".into()),
+                    url: Some("https://rapla.dhbw.de/rapla/eventinfo?id=abc&lang=de".into()),
+                },
+                Event {
+                    uid: "rapla-0123456789abcdef".into(),
+                    date: NaiveDate::from_ymd_opt(2024, 12, 2).unwrap(),
+                    start: NaiveTime::from_hms_opt(13, 0, 0).unwrap(),
+                    end: NaiveTime::from_hms_opt(14, 30, 0).unwrap(),
+                    title: "Klausur".into(),
+                    location: None,
+                    organizer: None,
+                    description: None,
+                    kind: None,
+                    course: None,
+                    remarks: None,
+                    url: None,
+                },
+            ],
+            skipped: Vec::new(),
+        }
+    }
+
+    fn parse_ics(ics: &str) -> Tree {
+        let mut stack = vec![Tree::default()];
+        for line in ics.replace("\r\n ", "").split("\r\n") {
+            if line.is_empty() {
+                continue;
+            }
+            let (name, value) = line.split_once(':').unwrap();
+            let mut parameters = name.split(';');
+            let name = parameters.next().unwrap().to_lowercase();
+            let tzid = parameters
+                .find_map(|parameter| parameter.strip_prefix("TZID="))
+                .map(String::from);
+
+            match name.as_str() {
+                "begin" => stack.push(Tree {
+                    name: value.to_lowercase(),
+                    ..Tree::default()
+                }),
+                "end" => {
+                    let component = stack.pop().unwrap();
+                    assert_eq!(component.name, value.to_lowercase());
+                    stack.last_mut().unwrap().components.push(component);
+                }
+                _ => {
+                    let properties = &mut stack.last_mut().unwrap().properties;
+                    properties.push((name, tzid, value.to_string()));
+                }
+            }
+        }
+
+        let mut root = stack.pop().unwrap();
+        assert!(stack.is_empty() && root.components.len() == 1);
+        root.components.remove(0).sorted()
+    }
+
+    /// Converts a jCal value to how it's written in ICS.
+    fn ics_value(type_name: &str, value: &Json) -> String {
+        match type_name {
+            "text" => escape_text(value.as_str().unwrap()).into_owned(),
+            "date-time" => value.as_str().unwrap().replace(['-', ':'], ""),
+            "utc-offset" => value.as_str().unwrap().replace(':', ""),
+            "recur" => format!(
+                "FREQ={};BYMONTH={};BYDAY={}",
+                value["freq"].as_str().unwrap(),
+                value["bymonth"],
+                value["byday"].as_str().unwrap()
+            ),
+            _ => value.as_str().unwrap().to_string(),
+        }
+    }
+
+    fn parse_jcal(jcal: &Json) -> Tree {
+        let [name, properties, components] = jcal.as_array().unwrap().as_slice() else {
+            panic!("expected component, got {jcal}");
+        };
+
+        let properties = properties.as_array().unwrap().iter().map(|property| {
+            let [name, parameters, type_name, value] = property.as_array().unwrap().as_slice()
+            else {
+                panic!("expected property, got {property}");
+            };
+            let tzid = parameters
+                .get("tzid")
+                .map(|tzid| tzid.as_str().unwrap().into());
+            let type_name = type_name.as_str().unwrap();
+            (
+                name.as_str().unwrap().into(),
+                tzid,
+                ics_value(type_name, value),
+            )
+        });
+
+        Tree {
+            name: name.as_str().unwrap().into(),
+            properties: properties.collect(),
+            components: components
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(parse_jcal)
+                .collect(),
+        }
+        .sorted()
+    }
+
+    fn parse_xcal(xcal: &str) -> Tree {
+        fn child<'a, 'input>(
+            node: roxmltree::Node<'a, 'input>,
+            name: &str,
+        ) -> Option<roxmltree::Node<'a, 'input>> {
+            node.children()
+                .find(|child| child.tag_name().name() == name)
+        }
+
+        fn element(node: roxmltree::Node) -> Tree {
+            let properties = child(node, "properties")
+                .unwrap()
+                .children()
+                .map(|property| {
+                    let tzid = child(property, "parameters")
+                        .and_then(|parameters| child(parameters, "tzid"))
+                        .and_then(|tzid| child(tzid, "text")?.text())
+                        .map(String::from);
+
+                    let value = property
+                        .children()
+                        .find(|child| child.tag_name().name() != "parameters")
+                        .unwrap();
+                    let type_name = value.tag_name().name();
+                    let json = if type_name == "recur" {
+                        let part = |name| child(value, name).unwrap().text().unwrap();
+                        json!({
+                            "freq": part("freq"),
+                            "bymonth": part("bymonth").parse::<u32>().unwrap(),
+                            "byday": part("byday"),
+                        })
+                    } else {
+                        json!(value.text().unwrap_or_default())
+                    };
+
+                    let name = property.tag_name().name().to_string();
+                    (name, tzid, ics_value(type_name, &json))
+                });
+
+            let components = child(node, "components")
+                .map(|components| components.children().map(element).collect())
+                .unwrap_or_default();
+
+            Tree {
+                name: node.tag_name().name().into(),
+                properties: properties.collect(),
+                components,
+            }
+            .sorted()
+        }
+
+        let document = roxmltree::Document::parse(xcal).unwrap();
+        let root = document.root_element();
+        assert_eq!(root.tag_name().name(), "icalendar");
+        assert_eq!(
+            root.tag_name().namespace(),
+            Some("urn:ietf:params:xml:ns:icalendar-2.0")
+        );
+        element(root.first_element_child().unwrap())
+    }
+
+    #[test]
+    fn jcal_matches_ics() {
+        let calendar = calendar();
+        let ics = parse_ics(&calendar.to_ics().to_string());
+        assert_eq!(parse_jcal(&calendar.to_jcal()), ics);
+    }
+
+    #[test]
+    fn xcal_matches_ics() {
+        let calendar = calendar();
+        let ics = parse_ics(&calendar.to_ics().to_string());
+        assert_eq!(parse_xcal(&calendar.to_xcal()), ics);
+    }
+
+    #[test]
+    fn ics_has_timezone_and_events() {
+        let ics = parse_ics(&calendar().to_ics().to_string());
+        let names = ics
+            .components
+            .iter()
+            .map(|component| component.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["vtimezone", "vevent", "vevent"]);
+
+        let timezone = &ics.components[0];
+        assert!(
+            timezone
+                .properties
+                .contains(&("tzid".into(), None, TZID.into()))
+        );
+        let transitions = timezone
+            .components
+            .iter()
+            .map(|component| component.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(transitions, ["daylight", "standard"]);
+    }
+
+    #[test]
+    fn escapes_text() {
+        let ics = parse_ics(&calendar().to_ics().to_string());
+        let property = |name: &str| {
+            ics.components[1]
+                .properties
+                .iter()
+                .find(|property| property.0 == name)
+                .map(|property| property.2.as_str())
+        };
+
+        assert_eq!(
+            property("summary"),
+            Some(r"Mathe\, Analysis\; Teil 1 <Übung>")
+        );
+        assert_eq!(property("location"), Some(r"A1\\B"));
+        assert_eq!(
+            property("description"),
+            Some(r"Bitte Laptop mitbringen\nund & Skript\n\nA1\, A2")
+        );
+        assert!(calendar().to_xcal().contains("Teil 1 &lt;Übung&gt;"));
+    }
+
+    #[test]
+    fn stamps_events_in_utc() {
+        let calendar = calendar();
+        let ics = calendar.to_ics().to_string();
+        assert!(ics.contains("DTSTAMP:20240930T060000Z\r\n"));
+        assert!(ics.contains("DTSTAMP:20241202T120000Z\r\n"));
+        assert!(ics.contains("DTSTART;TZID=Europe/Berlin:20240930T080000\r\n"));
+
+        let jcal = calendar.to_jcal();
+        assert_eq!(
+            jcal[2][1][1][1],
+            json!(["dtstamp", {}, "date-time", "2024-09-30T06:00:00Z"])
+        );
+    }
 }
@@ -0,0 +1,200 @@
+use regex::Regex;
+
+use crate::calendar::{Calendar, Event};
+use crate::resolver::InvalidParameter;
+
+/// Proxy-specific query parameters for filtering events, each of which may be repeated.
+#[derive(Debug, Clone, Default)]
+pub struct FilterQuery {
+    include: Vec<String>,
+    exclude: Vec<String>,
+    include_regex: Vec<String>,
+    exclude_regex: Vec<String>,
+}
+
+impl FilterQuery {
+    /// Collects the filter parameters of a query string, other parameters are ignored.
+    pub fn from_query_string(query: &str) -> Self {
+        let mut filter = Self::default();
+        let pairs = serde_urlencoded::from_str::<Vec<(String, String)>>(query).unwrap_or_default();
+        for (key, value) in pairs {
+            match key.as_str() {
+                "include" => filter.include.push(value),
+                "exclude" => filter.exclude.push(value),
+                "include_regex" => filter.include_regex.push(value),
+                "exclude_regex" => filter.exclude_regex.push(value),
+                _ => {}
+            }
+        }
+        filter
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    /// Case-insensitive, the needle is stored lowercase.
+    Substring(String),
+    Regex(Regex),
+}
+
+/// Keeps events whose title, location or organizer match any of the `include`
+/// parameters (if there are any) and none of the `exclude` parameters.
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+    include: Vec<Matcher>,
+    exclude: Vec<Matcher>,
+}
+
+impl Matcher {
+    fn is_match(&self, haystack: &str) -> bool {
+        match self {
+            Self::Substring(needle) => haystack.to_lowercase().contains(needle),
+            Self::Regex(regex) => regex.is_match(haystack),
+        }
+    }
+
+    fn matches_event(&self, event: &Event) -> bool {
+        [
+            Some(&event.title),
+            event.location.as_ref(),
+            event.organizer.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .any(|field| self.is_match(field))
+    }
+}
+
+impl EventFilter {
+    /// Fails with every regular expression that doesn't compile.
+    pub fn from_query(query: FilterQuery) -> Result<Self, Vec<InvalidParameter>> {
+        let mut invalid = Vec::new();
+        let mut matchers = |substrings: Vec<String>, regexes: Vec<String>, name| {
+            let substrings = substrings
+                .into_iter()
+                .map(|needle| Matcher::Substring(needle.to_lowercase()));
+            let regexes = regexes
+                .into_iter()
+                .filter_map(|regex| match Regex::new(&regex) {
+                    Ok(regex) => Some(Matcher::Regex(regex)),
+                    Err(_) => {
+                        invalid.push(InvalidParameter {
+                            name,
+                            value: regex,
+                            expected: "a valid regular expression",
+                        });
+                        None
+                    }
+                });
+            substrings.chain(regexes).collect::<Vec<_>>()
+        };
+
+        let filter = Self {
+            include: matchers(query.include, query.include_regex, "include_regex"),
+            exclude: matchers(query.exclude, query.exclude_regex, "exclude_regex"),
+        };
+
+        if invalid.is_empty() {
+            Ok(filter)
+        } else {
+            Err(invalid)
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    pub fn apply(&self, calendar: &mut Calendar) {
+        if self.is_empty() {
+            return;
+        }
+
+        calendar.events.retain(|event| {
+            (self.include.is_empty() || self.include.iter().any(|m| m.matches_event(event)))
+                && !self.exclude.iter().any(|m| m.matches_event(event))
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, NaiveTime};
+
+    use super::*;
+
+    fn event(title: &str, location: Option<&str>) -> Event {
+        Event {
+            uid: title.into(),
+            date: NaiveDate::from_ymd_opt(2024, 9, 30).unwrap(),
+            start: NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(10, 0, 0).unwrap(),
+            title: title.into(),
+            location: location.map(String::from),
+            organizer: None,
+            description: None,
+            kind: None,
+            course: None,
+            remarks: None,
+            url: None,
+        }
+    }
+
+    fn filtered(query: &str) -> Vec<String> {
+        let mut calendar = Calendar {
+            name: "TINF22B".into(),
+            events: vec![
+                event("Mathe", Some("A1")),
+                event("Wahlfach Robotik", Some("B2")),
+                event("Wahlfach Kryptographie", None),
+                event("Projekt", Some("Labor")),
+            ],
+            skipped: Vec::new(),
+        };
+        EventFilter::from_query(FilterQuery::from_query_string(query))
+            .unwrap()
+            .apply(&mut calendar);
+        calendar
+            .events
+            .into_iter()
+            .map(|event| event.title)
+            .collect()
+    }
+
+    #[test]
+    fn keeps_everything_without_filters() {
+        assert_eq!(filtered("key=a&salt=b").len(), 4);
+    }
+
+    #[test]
+    fn accepts_repeated_parameters() {
+        assert_eq!(
+            filtered("include=mathe&include=labor"),
+            ["Mathe", "Projekt"]
+        );
+        assert_eq!(
+            filtered("exclude=robotik&exclude_regex=^Kry&exclude_regex=Projekt$"),
+            ["Mathe", "Wahlfach Kryptographie"]
+        );
+    }
+
+    #[test]
+    fn excludes_after_including() {
+        assert_eq!(
+            filtered("include_regex=^Wahlfach&exclude=b2"),
+            ["Wahlfach Kryptographie"]
+        );
+    }
+
+    #[test]
+    fn reports_every_invalid_regex() {
+        let query =
+            FilterQuery::from_query_string("include_regex=(&include_regex=ok&exclude_regex=[");
+        let invalid = EventFilter::from_query(query).unwrap_err();
+        let invalid = invalid
+            .iter()
+            .map(|parameter| (parameter.name, parameter.value.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(invalid, [("include_regex", "("), ("exclude_regex", "[")]);
+    }
+}
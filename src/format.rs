@@ -0,0 +1,121 @@
+use axum::http::header::ACCEPT;
+use axum::http::{HeaderMap, Uri};
+use axum::response::{Html, IntoResponse, Response};
+use serde::Deserialize;
+
+use crate::calendar::Calendar;
+use crate::html;
+use crate::proxy::SkippedEvents;
+
+const SKIPPED_EVENTS_HEADER: &str = "X-Rapla-Skipped-Events";
+
+/// How a calendar is rendered, chosen by the `format` query parameter or the
+/// `Accept` header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Ics,
+    Json,
+    /// RFC 7265
+    Jcal,
+    /// RFC 6321
+    Xcal,
+    /// A human-readable agenda, for browsers.
+    Html,
+}
+
+impl Format {
+    const MEDIA_TYPES: &[(&str, Self)] = &[
+        ("text/calendar", Self::Ics),
+        ("application/json", Self::Json),
+        ("application/calendar+json", Self::Jcal),
+        ("application/calendar+xml", Self::Xcal),
+        ("text/html", Self::Html),
+    ];
+
+    /// Picks the explicitly requested format, or else the acceptable media type
+    /// with the highest quality. Anything unknown falls back to ICS, which is
+    /// what calendar clients expect.
+    pub fn negotiate(requested: Option<Self>, headers: &HeaderMap) -> Self {
+        if let Some(format) = requested {
+            return format;
+        }
+
+        let Some(accept) = headers.get(ACCEPT).and_then(|value| value.to_str().ok()) else {
+            return Self::default();
+        };
+
+        let mut best = (Self::default(), 0.0);
+        for range in accept.split(',') {
+            let mut params = range.split(';').map(str::trim);
+            let media_type = params.next().unwrap_or_default();
+            let quality = params
+                .find_map(|param| param.strip_prefix("q="))
+                .and_then(|quality| quality.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            let format = Self::MEDIA_TYPES
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(media_type))
+                .map(|&(_, format)| format);
+
+            // Wildcards count towards the default, so `*/*` beats a lower rated JSON.
+            let format = match format {
+                Some(format) => format,
+                None if media_type.ends_with("/*") => Self::default(),
+                None => continue,
+            };
+
+            if quality > best.1 {
+                best = (format, quality);
+            }
+        }
+
+        best.0
+    }
+
+    /// Renders the calendar requested at `uri`, which the HTML view links back to.
+    pub fn render(self, calendar: &Calendar, uri: &Uri, headers: &HeaderMap) -> Response {
+        let mut response = match self {
+            Self::Ics => (
+                [("content-type", "text/calendar")],
+                calendar.to_ics().to_string(),
+            )
+                .into_response(),
+            Self::Json => (
+                [("content-type", "application/json")],
+                serde_json::to_string(&calendar.to_json()).expect("calendar should serialize"),
+            )
+                .into_response(),
+            Self::Jcal => (
+                [("content-type", "application/calendar+json")],
+                calendar.to_jcal().to_string(),
+            )
+                .into_response(),
+            Self::Xcal => (
+                [("content-type", "application/calendar+xml")],
+                calendar.to_xcal(),
+            )
+                .into_response(),
+            Self::Html => Html(html::render_calendar(calendar, uri, headers)).into_response(),
+        };
+
+        if !calendar.skipped.is_empty() {
+            response.headers_mut().insert(
+                SKIPPED_EVENTS_HEADER,
+                calendar
+                    .skipped
+                    .len()
+                    .to_string()
+                    .parse()
+                    .expect("header value did not parse"),
+            );
+            response.extensions_mut().insert(SkippedEvents(
+                calendar.skipped.iter().map(ToString::to_string).collect(),
+            ));
+        }
+
+        response
+    }
+}
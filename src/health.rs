@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::Router;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use tokio::sync::Mutex;
+
+use crate::cache::CalendarCache;
+use crate::proxy::UpstreamClient;
+use crate::resolver::ResolverConfig;
+
+/// Upper bound for a single probe, health checks usually time out after a few seconds.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone)]
+pub struct HealthState {
+    cache: CalendarCache,
+    client: UpstreamClient,
+    /// Lists the upstream hosts to probe.
+    resolver: Arc<ResolverConfig>,
+    /// How long a probe result is reused, upstream isn't probed if unset.
+    probe_ttl: Option<Duration>,
+    /// Last probe of each upstream host.
+    probes: Arc<Mutex<HashMap<String, Probe>>>,
+}
+
+struct Probe {
+    checked: Instant,
+    result: Result<(), String>,
+}
+
+impl HealthState {
+    pub fn new(
+        cache: CalendarCache,
+        client: UpstreamClient,
+        resolver: Arc<ResolverConfig>,
+        probe_ttl: Option<Duration>,
+    ) -> Self {
+        Self {
+            cache,
+            client,
+            resolver,
+            probe_ttl,
+            probes: Arc::default(),
+        }
+    }
+}
+
+/// Serves `/healthz` and `/readyz`. Apply these after all middlewares, so
+/// health checks neither get resolved as calendars nor show up in logs and
+/// metrics.
+pub fn apply_routes(router: Router, state: HealthState) -> Router {
+    router
+        .route("/healthz", get(healthz_handler))
+        .route("/readyz", get(readyz_handler).with_state(state))
+}
+
+async fn healthz_handler() -> Response {
+    plain(StatusCode::OK, "ok\n".into())
+}
+
+/// Ready unless probing is enabled and an upstream host can't be reached.
+/// Probe results are reused for a while, so frequent checks don't turn into
+/// frequent requests to upstream.
+async fn readyz_handler(State(state): State<HealthState>) -> Response {
+    let mut ready = true;
+    let mut body = String::new();
+
+    let cache = &state.cache;
+    if cache.max_size() == 0 {
+        body.push_str("cache: disabled\n");
+    } else {
+        let _ = writeln!(
+            body,
+            "cache: {} calendars, {} of {} bytes{}",
+            cache.entry_count(),
+            cache.weighted_size(),
+            cache.max_size(),
+            if cache.is_persistent() {
+                ", persisted to disk"
+            } else {
+                ""
+            },
+        );
+    }
+
+    if let Some(ttl) = state.probe_ttl {
+        // Held while probing, so concurrent checks wait for the same probe.
+        let mut probes = state.probes.lock().await;
+        for host in &state.resolver.hosts {
+            let probe = match probes.get(host) {
+                Some(probe) if probe.checked.elapsed() < ttl => probe,
+                _ => {
+                    let result =
+                        match tokio::time::timeout(PROBE_TIMEOUT, state.client.probe(host)).await {
+                            Ok(result) => result.map_err(|err| err.to_string()),
+                            Err(_) => Err("upstream timed out".into()),
+                        };
+                    let checked = Instant::now();
+                    probes.insert(host.clone(), Probe { checked, result });
+                    &probes[host]
+                }
+            };
+
+            let age = probe.checked.elapsed().as_secs();
+            match &probe.result {
+                Ok(()) => {
+                    let _ = writeln!(body, "upstream {host}: reachable, checked {age}s ago");
+                }
+                Err(err) => {
+                    ready = false;
+                    let _ = writeln!(body, "upstream {host}: {err}, checked {age}s ago");
+                }
+            }
+        }
+    }
+
+    if ready {
+        plain(StatusCode::OK, format!("ready\n{body}"))
+    } else {
+        plain(
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("not ready\n{body}"),
+        )
+    }
+}
+
+fn plain(status: StatusCode, body: String) -> Response {
+    (
+        status,
+        [
+            ("content-type", "text/plain"),
+            ("cache-control", "no-store"),
+        ],
+        body,
+    )
+        .into_response()
+}
@@ -0,0 +1,184 @@
+use std::fmt::Write;
+
+use axum::http::header::HOST;
+use axum::http::{HeaderMap, Uri};
+use chrono::{Datelike, IsoWeek, NaiveDate, Utc, Weekday};
+use html_escape::{encode_double_quoted_attribute, encode_text};
+
+use crate::calendar::{Calendar, Event};
+
+const STYLE: &str = "
+body { font-family: system-ui, sans-serif; max-width: 60rem; margin: 2rem auto; padding: 0 1rem; }
+input { width: 100%; font-family: monospace; }
+table { border-collapse: collapse; width: 100%; margin-bottom: 1rem; }
+td { border-top: 1px solid #ddd; padding: 0.25rem 0.5rem; vertical-align: top; }
+td:first-child { white-space: nowrap; width: 7rem; }
+.muted { color: #666; }
+";
+
+/// The URL clients reach the proxy at for `uri`, as far as the request tells.
+pub fn external_url(uri: &Uri, headers: &HeaderMap) -> String {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    let scheme = header("x-forwarded-proto").unwrap_or("http");
+    let host = header("x-forwarded-host")
+        .or_else(|| header(HOST.as_str()))
+        .unwrap_or("localhost");
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+
+    format!("{scheme}://{host}{path}")
+}
+
+/// The same URL with the `webcal` scheme, which browsers hand to calendar apps.
+pub fn webcal_url(url: &str) -> String {
+    let (_, rest) = url.split_once("://").unwrap_or(("", url));
+    format!("webcal://{rest}")
+}
+
+/// The subscription URL of the calendar at `uri`, i.e. without the `format`
+/// parameter that selects this view.
+fn subscription_url(uri: &Uri, headers: &HeaderMap) -> String {
+    let query =
+        serde_urlencoded::from_str::<Vec<(String, String)>>(uri.query().unwrap_or_default())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(key, _)| key != "format")
+            .collect::<Vec<_>>();
+
+    let mut path = uri.path().to_string();
+    if !query.is_empty() {
+        path.push('?');
+        path.push_str(&serde_urlencoded::to_string(query).unwrap_or_default());
+    }
+
+    let uri = path.parse().unwrap_or_else(|_| uri.clone());
+    external_url(&uri, headers)
+}
+
+pub fn page(title: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html><html><head><meta charset="utf-8"><meta name="viewport" content="width=device-width, initial-scale=1"><title>{}</title><style>{STYLE}</style></head><body>{body}</body></html>"#,
+        encode_text(title)
+    )
+}
+
+/// Renders the calendar as an agenda grouped by week and day, along with the
+/// link to subscribe to it.
+pub fn render_calendar(calendar: &Calendar, uri: &Uri, headers: &HeaderMap) -> String {
+    let url = subscription_url(uri, headers);
+    let webcal = webcal_url(&url);
+
+    let mut body = String::new();
+    let _ = write!(
+        body,
+        r#"<h1>{name}</h1><p><a href="{webcal_attr}">Subscribe in your calendar app</a> or copy the link:</p><p><input readonly value="{url_attr}"></p>"#,
+        name = encode_text(&calendar.name),
+        webcal_attr = encode_double_quoted_attribute(&webcal),
+        url_attr = encode_double_quoted_attribute(&url),
+    );
+
+    // Events come in the order of Rapla's week tables, not necessarily by time.
+    let mut events = calendar.events.iter().collect::<Vec<_>>();
+    events.sort_by_key(|event| (event.date, event.start));
+
+    let _ = write!(body, r#"<p class="muted">{} events."#, events.len());
+    let today = Utc::now().date_naive();
+    if let Some(next) = events.iter().find(|event| event.date >= today) {
+        let _ = write!(
+            body,
+            r##" <a href="#{}">Jump to upcoming events</a>."##,
+            week_id(next.date.iso_week())
+        );
+    }
+    body.push_str("</p>");
+
+    if !calendar.skipped.is_empty() {
+        let _ = write!(
+            body,
+            "<details><summary>Events that could not be read: {}</summary><ul>",
+            calendar.skipped.len()
+        );
+        for err in &calendar.skipped {
+            let _ = write!(body, "<li>{}</li>", encode_text(&err.to_string()));
+        }
+        body.push_str("</ul></details>");
+    }
+
+    let mut week = None;
+    let mut day = None;
+    for event in events {
+        let event_week = event.date.iso_week();
+        if week != Some(event_week) {
+            week = Some(event_week);
+            let monday =
+                NaiveDate::from_isoywd_opt(event_week.year(), event_week.week(), Weekday::Mon)
+                    .expect("week should be valid");
+            let _ = write!(
+                body,
+                r#"<h2 id="{}">Week {}, starting {}</h2>"#,
+                week_id(event_week),
+                event_week.week(),
+                monday.format("%-d %B %Y"),
+            );
+        }
+
+        if day != Some(event.date) {
+            if day.is_some() {
+                body.push_str("</table>");
+            }
+            day = Some(event.date);
+            let _ = write!(body, "<h3>{}</h3><table>", event.date.format("%A, %-d %B"));
+        }
+
+        write_event(&mut body, event);
+    }
+    if day.is_some() {
+        body.push_str("</table>");
+    }
+
+    page(&calendar.name, &body)
+}
+
+fn week_id(week: IsoWeek) -> String {
+    format!("week-{}-{}", week.year(), week.week())
+}
+
+fn write_event(body: &mut String, event: &Event) {
+    let title = match &event.url {
+        Some(url) => format!(
+            r#"<a href="{}">{}</a>"#,
+            encode_double_quoted_attribute(url),
+            encode_text(&event.title)
+        ),
+        None => encode_text(&event.title).into_owned(),
+    };
+
+    let details = [
+        &event.kind,
+        &event.course,
+        &event.location,
+        &event.organizer,
+    ]
+    .into_iter()
+    .flatten()
+    .map(|detail| encode_text(detail).into_owned())
+    .collect::<Vec<_>>();
+    let details = if details.is_empty() {
+        String::new()
+    } else {
+        format!(r#"<br><span class="muted">{}</span>"#, details.join(" · "))
+    };
+
+    let notes = [&event.remarks, &event.description]
+        .into_iter()
+        .flatten()
+        .map(|note| encode_text(note).into_owned())
+        .collect::<Vec<_>>()
+        .join("<br>");
+
+    let _ = write!(
+        body,
+        r#"<tr><td>{} – {}</td><td><strong>{title}</strong>{details}</td><td class="muted">{notes}</td></tr>"#,
+        event.start.format("%H:%M"),
+        event.end.format("%H:%M"),
+    );
+}
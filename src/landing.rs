@@ -0,0 +1,123 @@
+use std::fmt::Write;
+use std::sync::Arc;
+
+use axum::Router;
+use axum::extract::{Query, State};
+use axum::http::{HeaderMap, Uri};
+use axum::response::Html;
+use axum::routing::get;
+use html_escape::{encode_double_quoted_attribute, encode_text};
+use serde::Deserialize;
+
+use crate::html::{external_url, page, webcal_url};
+use crate::proxy::Proxy;
+use crate::resolver::{ResolveError, ResolverConfig, UpstreamUrlComponents};
+
+const NOT_RAPLA: &str = "<p><strong>This doesn't look like a Rapla calendar link.</strong> It should start with <code>https://rapla.dhbw.de/rapla/</code> and contain <code>key</code> and <code>salt</code> or <code>user</code> and <code>file</code> parameters.</p>";
+
+#[derive(Debug, Deserialize)]
+struct LandingQuery {
+    url: Option<String>,
+}
+
+/// Serves the landing page at `/`, which turns a Rapla link into a proxy link
+/// after checking that the proxy can actually convert it.
+pub fn apply_routes(router: Router, proxy: Proxy, config: Arc<ResolverConfig>) -> Router {
+    router.route("/", get(landing_handler).with_state((proxy, config)))
+}
+
+async fn landing_handler(
+    State((proxy, config)): State<(Proxy, Arc<ResolverConfig>)>,
+    Query(query): Query<LandingQuery>,
+    headers: HeaderMap,
+) -> Html<String> {
+    let rapla_url = query.url.as_deref().map(str::trim).unwrap_or_default();
+
+    let mut body = format!(
+        r#"<h1>Rapla ICS Proxy</h1><p>Paste the link to your Rapla calendar to get a link you can subscribe to in your calendar app.</p><form><p><input name="url" placeholder="https://rapla.dhbw.de/rapla/..." value="{}" autofocus></p><p><button>Convert</button></p></form>"#,
+        encode_double_quoted_attribute(rapla_url)
+    );
+
+    if !rapla_url.is_empty() {
+        body.push_str(&convert(&proxy, &config, rapla_url, &headers).await);
+    }
+
+    Html(page("Rapla ICS Proxy", &body))
+}
+
+/// Checks the Rapla link and describes the outcome.
+async fn convert(
+    proxy: &Proxy,
+    config: &ResolverConfig,
+    rapla_url: &str,
+    headers: &HeaderMap,
+) -> String {
+    let Ok(uri) = rapla_url.parse::<Uri>() else {
+        return NOT_RAPLA.into();
+    };
+
+    let upstream = UpstreamUrlComponents::from_request_uri(&uri, config).and_then(|upstream| {
+        let host = upstream.custom_host().map(String::from);
+        Ok((host, upstream.generate_url(config)?))
+    });
+    let (host, upstream) = match upstream {
+        Ok(upstream) => upstream,
+        Err(ResolveError::NotRapla) => return NOT_RAPLA.into(),
+        Err(ResolveError::InvalidParameters(invalid)) => {
+            let mut result = String::from(
+                "<p><strong>Some parameters of the link are invalid:</strong></p><ul>",
+            );
+            for parameter in invalid {
+                let _ = write!(result, "<li>{}</li>", encode_text(&parameter.to_string()));
+            }
+            result.push_str("</ul>");
+            return result;
+        }
+    };
+
+    // Only the path and query are kept, the host becomes the proxy's. Other
+    // Rapla hosts than the default one are kept in the path instead.
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+    let path = match host {
+        Some(host) => format!("/https://{host}{path}"),
+        None => path.to_string(),
+    };
+
+    let lookup = proxy.fetch(&upstream).await;
+    let mut calendar = match &lookup.result {
+        Ok(calendar) => calendar.as_ref().clone(),
+        Err(err) => {
+            return format!(
+                "<p><strong>The link looks right, but the calendar can't be loaded:</strong> {}.</p>",
+                encode_text(&err.to_string())
+            );
+        }
+    };
+    upstream.apply(&mut calendar);
+
+    let url = external_url(&path.parse().unwrap_or_default(), headers);
+    let webcal = webcal_url(&url);
+    let separator = if url.contains('?') { '&' } else { '?' };
+
+    let mut result = format!(
+        r#"<h2>{}</h2><p>{} events found."#,
+        encode_text(&calendar.name),
+        calendar.events.len()
+    );
+    if !calendar.skipped.is_empty() {
+        let _ = write!(
+            result,
+            " {} events could not be read and are left out.",
+            calendar.skipped.len()
+        );
+    }
+    let _ = write!(
+        result,
+        r#" <a href="{preview}">Preview the calendar</a>.</p><p>Subscribe to this link in your calendar app:</p><p><input readonly value="{url}"></p><p>Or <a href="{webcal}">open it in your calendar app directly</a>.</p>"#,
+        preview = encode_double_quoted_attribute(&format!("{url}{separator}format=html")),
+        url = encode_double_quoted_attribute(&url),
+        webcal = encode_double_quoted_attribute(&webcal),
+    );
+
+    result
+}
@@ -1,50 +1,377 @@
-use std::io::{self, Write};
+use std::str::FromStr;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Instant;
 
 use axum::Router;
 use axum::extract::{Request, State};
+use axum::http::Uri;
 use axum::middleware::{self, Next};
 use axum::response::Response;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use tracing::Instrument;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+use xxhash_rust::xxh3::xxh3_64;
 
-pub fn apply_middleware(router: Router) -> Router {
+use crate::proxy::{ErrorDetails, SkippedEvents};
+use crate::resolver::SECRET_PARAMETERS;
+
+/// How log lines are written to stderr.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// One JSON object per line, including the fields of enclosing spans.
+    #[default]
+    Json,
+    /// Multi-line output for humans.
+    Pretty,
+}
+
+impl FromStr for LogFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "pretty" => Ok(Self::Pretty),
+            _ => Err("expected json or pretty"),
+        }
+    }
+}
+
+/// Installs the global subscriber, filtered by `filter` directives such as
+/// `info` or `rapla_ical_proxy=debug`. Spans are also exported through
+/// `tracer_provider`, if given.
+pub fn init(format: LogFormat, filter: Option<&str>, tracer_provider: Option<&SdkTracerProvider>) {
+    let filter = match filter {
+        Some(filter) => EnvFilter::try_new(filter).unwrap_or_else(|err| {
+            eprintln!("Invalid $RAPLA_LOG: {err}");
+            std::process::exit(1);
+        }),
+        None => EnvFilter::new("info"),
+    };
+
+    let output = tracing_subscriber::fmt::layer().with_writer(std::io::stderr);
+    let output = match format {
+        LogFormat::Json => output
+            .json()
+            .flatten_event(true)
+            .with_current_span(false)
+            .with_span_list(true)
+            .boxed(),
+        LogFormat::Pretty => output.pretty().boxed(),
+    };
+
+    let export = tracer_provider.map(|provider| {
+        tracing_opentelemetry::layer().with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+    });
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(output)
+        .with(export)
+        .init();
+}
+
+/// How the parameters that grant access to a calendar appear in logs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Redaction {
+    /// Logged as they are.
+    Full,
+    /// Replaced by a short hash, so requests for the same calendar can still
+    /// be told apart from others.
+    #[default]
+    Hashed,
+    /// Left out entirely.
+    Omitted,
+}
+
+impl FromStr for Redaction {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(Self::Full),
+            "hashed" => Ok(Self::Hashed),
+            "omitted" => Ok(Self::Omitted),
+            _ => Err("expected full, hashed or omitted"),
+        }
+    }
+}
+
+impl Redaction {
+    /// Redacts the URI's query parameters, those of Rapla links passed as
+    /// parameters, e.g. to `/merge`, and path segments holding a query, as
+    /// in `/caldav/<page>/<query>/`.
+    fn redact_uri(self, uri: &Uri) -> String {
+        if self == Self::Full {
+            return uri.to_string();
+        }
+
+        let mut redacted = uri
+            .path()
+            .split('/')
+            .map(|segment| {
+                if segment.contains('=') {
+                    self.redact_query(segment)
+                } else {
+                    segment.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        if let Some(query) = uri.query().map(|query| self.redact_query(query))
+            && !query.is_empty()
+        {
+            redacted.push('?');
+            redacted.push_str(&query);
+        }
+        redacted
+    }
+
+    fn redact_query(self, query: &str) -> String {
+        query
+            .split('&')
+            .filter_map(|pair| self.redact_pair(pair))
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    fn redact_pair(self, pair: &str) -> Option<String> {
+        let decoded = serde_urlencoded::from_str::<Vec<(String, String)>>(pair).unwrap_or_default();
+        let [(name, value)] = decoded.as_slice() else {
+            return Some(pair.to_string());
+        };
+
+        if SECRET_PARAMETERS.contains(&name.as_str()) {
+            let (raw_name, _) = pair.split_once('=').unwrap_or((pair, ""));
+            return match self {
+                Self::Full => Some(pair.to_string()),
+                Self::Hashed => Some(format!(
+                    "{raw_name}=redacted-{:08x}",
+                    xxh3_64(value.as_bytes()) >> 32
+                )),
+                Self::Omitted => None,
+            };
+        }
+
+        // Values may be Rapla links themselves.
+        if !value.contains('=') {
+            return Some(pair.to_string());
+        }
+        let nested = match value.split_once('?') {
+            Some((base, query)) => format!("{base}?{}", self.redact_query(query)),
+            None => self.redact_query(value),
+        };
+        if nested == *value {
+            return Some(pair.to_string());
+        }
+        Some(serde_urlencoded::to_string([(name, nested)]).unwrap_or_default())
+    }
+}
+
+struct LoggingState {
+    request_counter: AtomicU64,
+    redaction: Redaction,
+}
+
+pub fn apply_middleware(router: Router, redaction: Redaction) -> Router {
     router.route_layer(middleware::from_fn_with_state(
-        Arc::new(AtomicU64::new(0)),
+        Arc::new(LoggingState {
+            request_counter: AtomicU64::new(0),
+            redaction,
+        }),
         logging_middleware,
     ))
 }
 
 async fn logging_middleware(
-    State(request_counter): State<Arc<AtomicU64>>,
+    State(state): State<Arc<LoggingState>>,
     request: Request,
     next: Next,
 ) -> Response {
-    let request_id = request_counter.fetch_add(1, Ordering::Relaxed);
-    let request_path = request.uri().to_string();
+    let request_id = state.request_counter.fetch_add(1, Ordering::Relaxed);
     let user_agent = request
         .headers()
         .get("user-agent")
         .and_then(|v| v.to_str().ok().map(|v| v.to_owned()));
 
+    // Everything logged while handling the request is nested in this span.
+    let span = tracing::info_span!(
+        "request",
+        request_id,
+        method = %request.method(),
+        path = state.redaction.redact_uri(request.uri()),
+        user_agent,
+        otel.kind = "server",
+        otel.status_code = tracing::field::Empty,
+    );
+    crate::telemetry::continue_trace(&span, request.headers());
+
     let start_time = Instant::now();
-    let response = next.run(request).await;
-
-    let json = serde_json::json!({
-        "request_id": request_id,
-        "status_code": response.status().as_u16(),
-        "cached": response.headers().get("x-cache-age").is_some(),
-        "processing_time": Instant::now().duration_since(start_time).as_secs_f64(),
-        "user_agent": user_agent,
-        "path": request_path,
+    let response = next.run(request).instrument(span.clone()).await;
+
+    let error = response.extensions().get::<ErrorDetails>();
+    let skipped = response.extensions().get::<SkippedEvents>();
+    if response.status().is_server_error() {
+        span.record("otel.status_code", "error");
+    }
+    span.in_scope(|| {
+        tracing::info!(
+            status_code = response.status().as_u16(),
+            cached = response.headers().get("x-cache-age").is_some(),
+            processing_time = start_time.elapsed().as_secs_f64(),
+            error = error.map(|details| details.0.as_str()),
+            skipped_events = skipped.map(|skipped| tracing::field::debug(&skipped.0)),
+            "request handled",
+        );
     });
 
-    let Ok(mut buf) = serde_json::to_vec(&json) else {
-        return response;
-    };
+    response
+}
 
-    buf.push(b'\n');
-    let _ = io::stderr().lock().write_all(&buf);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    response
+    fn redact(redaction: Redaction, uri: &str) -> String {
+        redaction.redact_uri(&uri.parse().unwrap())
+    }
+
+    fn hash(secret: &str) -> String {
+        format!("redacted-{:08x}", xxh3_64(secret.as_bytes()) >> 32)
+    }
+
+    #[test]
+    fn keeps_everything_in_full_mode() {
+        let uri = "/rapla/calendar?key=abc&salt=def&strict=true";
+        assert_eq!(redact(Redaction::Full, uri), uri);
+    }
+
+    #[test]
+    fn hashes_secret_parameters() {
+        let redacted = redact(
+            Redaction::Hashed,
+            "/rapla/calendar?key=abc&salt=def&strict=true",
+        );
+        assert_eq!(
+            redacted,
+            format!(
+                "/rapla/calendar?key={}&salt={}&strict=true",
+                hash("abc"),
+                hash("def")
+            )
+        );
+
+        let v2 = redact(Redaction::Hashed, "/rapla/calendar?user=me&file=TINF22B");
+        assert_eq!(
+            v2,
+            format!(
+                "/rapla/calendar?user={}&file={}",
+                hash("me"),
+                hash("TINF22B")
+            )
+        );
+    }
+
+    #[test]
+    fn hashes_decoded_values() {
+        // The same calendar gets the same hash, however its parameters are encoded.
+        assert_eq!(
+            redact(Redaction::Hashed, "/rapla/calendar?key=a%2Bb"),
+            format!("/rapla/calendar?key={}", hash("a+b"))
+        );
+        assert_eq!(
+            redact(Redaction::Hashed, "/rapla/calendar?key=a%20b"),
+            redact(Redaction::Hashed, "/rapla/calendar?key=a+b")
+        );
+    }
+
+    #[test]
+    fn omits_secret_parameters() {
+        assert_eq!(
+            redact(
+                Redaction::Omitted,
+                "/rapla/calendar?key=abc&salt=def&strict=true"
+            ),
+            "/rapla/calendar?strict=true"
+        );
+        assert_eq!(
+            redact(Redaction::Omitted, "/rapla/calendar?key=abc&salt=def"),
+            "/rapla/calendar"
+        );
+    }
+
+    #[test]
+    fn redacts_links_in_path() {
+        assert_eq!(
+            redact(
+                Redaction::Hashed,
+                "/https://rapla-ravensburg.dhbw.de/rapla/calendar?key=abc&salt=def"
+            ),
+            format!(
+                "/https://rapla-ravensburg.dhbw.de/rapla/calendar?key={}&salt={}",
+                hash("abc"),
+                hash("def")
+            )
+        );
+    }
+
+    #[test]
+    fn redacts_nested_merge_sources() {
+        let source = |query: &str| format!("https://rapla.dhbw.de/rapla/calendar?{query}");
+        let uri = format!(
+            "/merge?{}",
+            serde_urlencoded::to_string([
+                ("source", source("key=abc&salt=def").as_str()),
+                ("source", source("user=me&file=TINF22B").as_str()),
+                ("name", "Electives"),
+            ])
+            .unwrap()
+        );
+
+        let expected = |redacted: [&str; 2]| {
+            let [first, second] = redacted;
+            format!(
+                "/merge?{}",
+                serde_urlencoded::to_string([
+                    ("source", source(first).as_str()),
+                    ("source", source(second).as_str()),
+                    ("name", "Electives"),
+                ])
+                .unwrap()
+            )
+        };
+
+        assert_eq!(
+            redact(Redaction::Hashed, &uri),
+            expected([
+                &format!("key={}&salt={}", hash("abc"), hash("def")),
+                &format!("user={}&file={}", hash("me"), hash("TINF22B")),
+            ])
+        );
+        assert_eq!(redact(Redaction::Omitted, &uri), expected(["", ""]));
+        assert_eq!(redact(Redaction::Full, &uri), uri);
+    }
+
+    #[test]
+    fn redacts_caldav_paths() {
+        assert_eq!(
+            redact(
+                Redaction::Hashed,
+                "/caldav/rapla-ravensburg.dhbw.de/calendar/key=abc&salt=def/rapla-1.ics"
+            ),
+            format!(
+                "/caldav/rapla-ravensburg.dhbw.de/calendar/key={}&salt={}/rapla-1.ics",
+                hash("abc"),
+                hash("def")
+            )
+        );
+        assert_eq!(
+            redact(Redaction::Omitted, "/caldav/calendar/key=abc&salt=def/"),
+            "/caldav/calendar//"
+        );
+    }
 }
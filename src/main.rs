@@ -1,35 +1,79 @@
 mod cache;
+mod caldav;
 mod calendar;
+mod filter;
+mod format;
+mod health;
+mod html;
+mod landing;
 mod logging;
+mod merge;
+mod metrics;
 mod parser;
 mod proxy;
 mod resolver;
+mod store;
+mod telemetry;
 
 use std::env::{self, VarError};
 use std::fmt::Display;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use axum::Router;
 use tokio::net::TcpListener;
 use tokio::time::Duration;
 
+use crate::cache::{CacheConfig, CalendarCache};
+use crate::health::HealthState;
+use crate::logging::{LogFormat, Redaction};
+use crate::proxy::{Proxy, UpstreamClient, UpstreamConfig};
+use crate::resolver::ResolverConfig;
+use crate::store::DiskStore;
+
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
+    let tracer_provider = crate::telemetry::tracer_provider();
+    crate::logging::init(
+        getenv::<LogFormat>("RAPLA_LOG_FORMAT").unwrap_or_default(),
+        getenv::<String>("RAPLA_LOG").as_deref(),
+        tracer_provider.as_ref(),
+    );
+
+    let default_config = ResolverConfig::default();
+    let host_list = |key| {
+        getenv::<String>(key).map(|hosts| {
+            hosts
+                .split(',')
+                .map(str::trim)
+                .filter(|host| !host.is_empty())
+                .map(String::from)
+                .collect()
+        })
+    };
+    let resolver_config = Arc::new(ResolverConfig {
+        hosts: host_list("RAPLA_HOSTS").unwrap_or(default_config.hosts),
+        weekly_hosts: host_list("RAPLA_WEEKLY_HOSTS").unwrap_or(default_config.weekly_hosts),
+        past_weeks: getenv("RAPLA_PAST_WEEKS").unwrap_or(default_config.past_weeks),
+        future_weeks: getenv("RAPLA_FUTURE_WEEKS").unwrap_or(default_config.future_weeks),
+    });
+
     // Single-shot debug mode for parser development.
     #[cfg(debug_assertions)]
     if let Some(uri) = getenv("RAPLA_DEBUG") {
-        use crate::proxy::{build_client, handle};
+        use crate::proxy::handle;
         use crate::resolver::UpstreamUrlComponents;
 
-        let calendar = handle(
-            &build_client(),
-            UpstreamUrlComponents::from_request_uri(&uri)
-                .expect("couldn't resolve upstream")
-                .generate_url(),
-        )
-        .await
-        .expect("couldn't handle request");
+        let upstream = UpstreamUrlComponents::from_request_uri(&uri, &resolver_config)
+            .and_then(|upstream| upstream.generate_url(&resolver_config))
+            .expect("couldn't resolve upstream");
+
+        let mut calendar = handle(&UpstreamClient::new(UpstreamConfig::default()), &upstream)
+            .await
+            .expect("couldn't handle request");
+        upstream.apply(&mut calendar);
 
         eprintln!("{calendar:#?}");
 
@@ -39,20 +83,72 @@ async fn main() -> std::io::Result<()> {
     let address =
         getenv("RAPLA_ADDRESS").unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 8080)));
 
-    let cache_ttl = Duration::from_secs(getenv("RAPLA_CACHE_TTL").unwrap_or(3600));
-    let cache_capacity = getenv("RAPLA_CACHE_MAX_SIZE").unwrap_or(0);
+    let cache_config = CacheConfig {
+        ttl: Duration::from_secs(getenv("RAPLA_CACHE_TTL").unwrap_or(3600)),
+        error_ttl: Duration::from_secs(getenv("RAPLA_CACHE_ERROR_TTL").unwrap_or(300)),
+        retention: Duration::from_secs(getenv("RAPLA_CACHE_RETENTION").unwrap_or(86400)),
+        max_capacity: getenv("RAPLA_CACHE_MAX_SIZE").unwrap_or(0),
+    };
+    let cache_store = getenv::<PathBuf>("RAPLA_CACHE_DIR").map(|dir| {
+        DiskStore::open(dir).unwrap_or_else(|err| {
+            eprintln!("Invalid $RAPLA_CACHE_DIR: {err}");
+            std::process::exit(1);
+        })
+    });
+
+    let default_upstream = UpstreamConfig::default();
+    let upstream_config = UpstreamConfig {
+        connect_timeout: getenv("RAPLA_UPSTREAM_CONNECT_TIMEOUT")
+            .map_or(default_upstream.connect_timeout, Duration::from_secs),
+        timeout: getenv("RAPLA_UPSTREAM_TIMEOUT")
+            .map_or(default_upstream.timeout, Duration::from_secs),
+        retries: getenv("RAPLA_UPSTREAM_RETRIES").unwrap_or(default_upstream.retries),
+        breaker_threshold: getenv("RAPLA_UPSTREAM_BREAKER_THRESHOLD")
+            .unwrap_or(default_upstream.breaker_threshold),
+        breaker_cooldown: getenv("RAPLA_UPSTREAM_BREAKER_COOLDOWN")
+            .map_or(default_upstream.breaker_cooldown, Duration::from_secs),
+    };
+
+    let cache = CalendarCache::new(cache_config, cache_store).await;
+    let client = UpstreamClient::new(upstream_config);
+    let proxy = Proxy::new(client.clone(), cache.clone());
+
+    let probe_ttl = getenv::<bool>("RAPLA_READY_PROBE")
+        .unwrap_or(false)
+        .then(|| Duration::from_secs(getenv("RAPLA_READY_PROBE_TTL").unwrap_or(30)));
+    let health = HealthState::new(cache.clone(), client, resolver_config.clone(), probe_ttl);
 
     // Middlewares are layered, i.e. the later it is applied the earlier it is called.
+    let feed = Router::new();
+    let feed = crate::proxy::apply_routes(feed, proxy.clone());
+    let feed = crate::resolver::apply_middleware(feed, resolver_config.clone());
+
+    // Routes other than the catch-all feed route don't go through the resolver.
     let router = Router::new();
-    let router = crate::proxy::apply_routes(router);
-    let router = crate::cache::apply_middleware(router, (cache_ttl, cache_capacity));
-    let router = crate::resolver::apply_middleware(router);
-    let router = crate::logging::apply_middleware(router);
+    let router = crate::landing::apply_routes(router, proxy.clone(), resolver_config.clone());
+    let router = crate::merge::apply_routes(router, proxy.clone(), resolver_config.clone());
+    let router = crate::caldav::apply_routes(router, proxy, resolver_config);
+    let router = crate::metrics::apply_routes(router, cache);
+    let router = router.merge(feed);
+    let router = crate::cache::apply_middleware(router);
+    let router = crate::metrics::apply_middleware(router);
+    let redaction = getenv::<Redaction>("RAPLA_LOG_SECRETS").unwrap_or_default();
+    let router = crate::logging::apply_middleware(router, redaction);
+    let router = crate::health::apply_routes(router, health);
 
     let listener = TcpListener::bind(address).await?;
-    axum::serve(listener, router)
+    let result = axum::serve(listener, router)
         .with_graceful_shutdown(shutdown_signal())
-        .await
+        .await;
+
+    // Sends the spans that are still waiting to be exported.
+    if let Some(provider) = tracer_provider
+        && let Err(err) = provider.shutdown()
+    {
+        eprintln!("Can't export remaining traces: {err}");
+    }
+
+    result
 }
 
 fn getenv<T: FromStr>(key: &str) -> Option<T>
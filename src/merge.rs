@@ -0,0 +1,261 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use axum::Router;
+use axum::extract::{Request, State};
+use axum::http::Uri;
+use axum::response::Response;
+use axum::routing::get;
+use serde::Deserialize;
+
+use crate::calendar::Calendar;
+use crate::filter::{EventFilter, FilterQuery};
+use crate::format::Format;
+use crate::proxy::Proxy;
+use crate::resolver::{
+    ResolveError, ResolverConfig, UpstreamUrlComponents, bad_request, parse_format,
+};
+
+const MAX_SOURCES: usize = 16;
+
+/// Everything but the repeated `source` and filter parameters, which are collected separately.
+#[derive(Debug, Deserialize)]
+struct MergeQuery {
+    name: Option<String>,
+    format: Option<String>,
+}
+
+/// Serves `/merge?source=<url>&source=<url>...`, combining several calendars into one.
+///
+/// Sources are resolved and cached exactly like single feeds, so merged feeds
+/// share cached calendars with single feeds and each other.
+pub fn apply_routes(router: Router, proxy: Proxy, config: Arc<ResolverConfig>) -> Router {
+    router.route("/merge", get(merge_handler).with_state((proxy, config)))
+}
+
+async fn merge_handler(
+    State((proxy, config)): State<(Proxy, Arc<ResolverConfig>)>,
+    request: Request,
+) -> Response {
+    let query = request.uri().query().unwrap_or_default();
+
+    let Ok(MergeQuery { name, format }) = serde_urlencoded::from_str(query) else {
+        return bad_request("Could not parse query parameters");
+    };
+    let mut invalid = Vec::new();
+    let format = parse_format(&mut invalid, format);
+    let filter = EventFilter::from_query(FilterQuery::from_query_string(query)).unwrap_or_else(
+        |mut filter_invalid| {
+            invalid.append(&mut filter_invalid);
+            EventFilter::default()
+        },
+    );
+    if !invalid.is_empty() {
+        return ResolveError::InvalidParameters(invalid).to_response(request.headers());
+    }
+    let format = Format::negotiate(format, request.headers());
+
+    let sources = serde_urlencoded::from_str::<Vec<(String, String)>>(query)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|(key, value)| (key == "source").then_some(value))
+        .collect::<Vec<_>>();
+
+    if sources.is_empty() || sources.len() > MAX_SOURCES {
+        return bad_request(&format!(
+            "Expected between 1 and {MAX_SOURCES} source parameters"
+        ));
+    }
+
+    let mut handles = Vec::with_capacity(sources.len());
+    for source in &sources {
+        let upstream = Uri::try_from(source.as_str())
+            .map_err(|_| ResolveError::NotRapla)
+            .and_then(|uri| UpstreamUrlComponents::from_request_uri(&uri, &config))
+            .and_then(|upstream| upstream.generate_url(&config));
+
+        let upstream = match upstream {
+            Ok(upstream) => upstream,
+            Err(ResolveError::NotRapla) => {
+                return bad_request(&format!("Could not determine upstream URL of {source}"));
+            }
+            Err(err) => return err.to_response(request.headers()),
+        };
+
+        let proxy = proxy.clone();
+        handles.push(tokio::spawn(async move {
+            (proxy.fetch(&upstream).await, upstream)
+        }));
+    }
+
+    let mut calendars = Vec::with_capacity(handles.len());
+    let mut freshness = None;
+    for handle in handles {
+        let (lookup, upstream) = handle.await.expect("source request panicked");
+
+        // Failing sources fail the merged calendar, with their original error.
+        let mut calendar = match lookup.result {
+            Ok(calendar) => Calendar::clone(&calendar),
+            Err(err) => return err.to_response(),
+        };
+
+        upstream.apply(&mut calendar);
+        calendars.push(calendar);
+        freshness = Some(match freshness {
+            Some(freshness) => lookup.freshness.combine(freshness),
+            None => lookup.freshness,
+        });
+    }
+
+    let mut calendar = merge(calendars, name);
+    filter.apply(&mut calendar);
+
+    let mut response = format.render(&calendar, request.uri(), request.headers());
+    if let Some(freshness) = freshness {
+        freshness.apply_headers(&mut response);
+    }
+    response
+}
+
+/// Concatenates the calendars' events, dropping events that already appeared
+/// in an earlier calendar, i.e. share a UID or date, time and title with one
+/// of its events. Parallel events within one calendar are all kept.
+fn merge(calendars: Vec<Calendar>, name: Option<String>) -> Calendar {
+    let name = name.unwrap_or_else(|| {
+        calendars
+            .iter()
+            .map(|calendar| calendar.name.as_str())
+            .collect::<Vec<_>>()
+            .join(" + ")
+    });
+
+    let mut seen_uids = HashSet::new();
+    let mut seen_slots = HashSet::new();
+    let mut events = Vec::new();
+    let mut skipped = Vec::new();
+
+    for calendar in &calendars {
+        let mut uids = HashSet::new();
+        let mut slots = HashSet::new();
+        for event in &calendar.events {
+            let uid = event.uid.as_str();
+            let slot = (event.date, event.start, event.end, event.title.as_str());
+            if !seen_uids.contains(uid) && !seen_slots.contains(&slot) {
+                events.push(event.clone());
+            }
+            // Remember both keys, even if the first one already marks a duplicate.
+            uids.insert(uid);
+            slots.insert(slot);
+        }
+        seen_uids.extend(uids);
+        seen_slots.extend(slots);
+        skipped.extend(calendar.skipped.iter().cloned());
+    }
+
+    events.sort_by_key(|event| (event.date, event.start));
+
+    Calendar {
+        name,
+        events,
+        skipped,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, NaiveTime};
+
+    use super::*;
+    use crate::calendar::Event;
+
+    fn event(uid: &str, day: u32, hour: u32, title: &str, location: &str) -> Event {
+        Event {
+            uid: uid.into(),
+            date: NaiveDate::from_ymd_opt(2024, 9, day).unwrap(),
+            start: NaiveTime::from_hms_opt(hour, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(hour + 2, 0, 0).unwrap(),
+            title: title.into(),
+            location: Some(location.into()),
+            organizer: None,
+            description: None,
+            kind: None,
+            course: None,
+            remarks: None,
+            url: None,
+        }
+    }
+
+    fn calendar(name: &str, events: Vec<Event>) -> Calendar {
+        Calendar {
+            name: name.into(),
+            events,
+            skipped: Vec::new(),
+        }
+    }
+
+    fn locations(calendar: &Calendar) -> Vec<&str> {
+        calendar
+            .events
+            .iter()
+            .map(|event| event.location.as_deref().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn keeps_parallel_events_of_one_calendar() {
+        let labs = calendar(
+            "TINF22B1",
+            vec![
+                event("a", 30, 8, "Labor", "R1"),
+                event("b", 30, 8, "Labor", "R2"),
+            ],
+        );
+        let merged = merge(vec![labs, calendar("Empty", Vec::new())], None);
+        assert_eq!(locations(&merged), ["R1", "R2"]);
+    }
+
+    #[test]
+    fn drops_events_of_earlier_calendars() {
+        let first = calendar(
+            "TINF22B1",
+            vec![
+                event("a", 30, 8, "Mathe", "A1"),
+                event("b", 30, 10, "Labor", "R1"),
+            ],
+        );
+        let second = calendar(
+            "TINF22B2",
+            vec![
+                // Same UID, moved in the other calendar's copy.
+                event("a", 30, 13, "Mathe", "A2"),
+                // Same slot under another UID.
+                event("c", 30, 10, "Labor", "R2"),
+                event("d", 27, 8, "Projekt", "B1"),
+                event("e", 27, 8, "Projekt", "B2"),
+            ],
+        );
+
+        let merged = merge(vec![first, second], None);
+        assert_eq!(merged.name, "TINF22B1 + TINF22B2");
+        // Sorted by time, parallel events of the second calendar are all kept.
+        assert_eq!(locations(&merged), ["B1", "B2", "A1", "R1"]);
+    }
+
+    #[test]
+    fn uses_given_name() {
+        let merged = merge(
+            vec![
+                calendar("TINF22B1", Vec::new()),
+                calendar("TINF22B2", Vec::new()),
+            ],
+            Some("Electives".into()),
+        );
+        assert_eq!(merged.name, "Electives");
+        assert!(
+            merged
+                .to_ics()
+                .to_string()
+                .contains("X-WR-CALNAME:Electives\r\n")
+        );
+    }
+}
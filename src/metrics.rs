@@ -0,0 +1,220 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use axum::Router;
+use axum::extract::{Request, State};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+
+use crate::cache::CalendarCache;
+use crate::parser::ParseError;
+
+/// Upper bounds of the duration histograms in seconds, Rapla is slow.
+const DURATION_BUCKETS: [f64; 12] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+/// Upper bounds of the events per calendar histogram.
+const EVENT_BUCKETS: [f64; 9] = [0.0, 10.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0];
+
+static METRICS: Metrics = Metrics {
+    responses: [const { AtomicU64::new(0) }; 500],
+    cache_hits: AtomicU64::new(0),
+    cache_misses: AtomicU64::new(0),
+    upstream_duration: Histogram::new(&DURATION_BUCKETS),
+    parse_duration: Histogram::new(&DURATION_BUCKETS),
+    parse_failures: [const { AtomicU64::new(0) }; 3],
+    events: Histogram::new(&EVENT_BUCKETS),
+};
+
+struct Metrics {
+    /// Responses by status code, offset by 100.
+    responses: [AtomicU64; 500],
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    upstream_duration: Histogram,
+    parse_duration: Histogram,
+    /// Parse errors by [`failure_kind`], including skipped events.
+    parse_failures: [AtomicU64; 3],
+    events: Histogram,
+}
+
+const FAILURE_KINDS: [&str; 3] = ["calendar", "week", "event"];
+
+fn failure_kind(err: &ParseError) -> usize {
+    match err {
+        ParseError::Calendar { .. } | ParseError::Page { .. } => 0,
+        ParseError::Week { .. } => 1,
+        ParseError::Event { .. } => 2,
+    }
+}
+
+struct Histogram {
+    bounds: &'static [f64],
+    /// Observations per bucket, the last one counts those above all bounds.
+    buckets: [AtomicU64; 13],
+    /// Bit pattern of the `f64` sum of all observations.
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    const fn new(bounds: &'static [f64]) -> Self {
+        assert!(bounds.len() < 13, "histogram has too many buckets");
+        Self {
+            bounds,
+            buckets: [const { AtomicU64::new(0) }; 13],
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, value: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        let _ = self
+            .sum
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sum| {
+                Some((f64::from_bits(sum) + value).to_bits())
+            });
+    }
+
+    /// Writes the histogram in the Prometheus text format, buckets are cumulative.
+    fn write(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} histogram");
+        let mut count = 0;
+        for (idx, bucket) in self.buckets[..=self.bounds.len()].iter().enumerate() {
+            count += bucket.load(Ordering::Relaxed);
+            match self.bounds.get(idx) {
+                Some(bound) => {
+                    let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {count}");
+                }
+                None => {
+                    let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}");
+                }
+            }
+        }
+        let sum = f64::from_bits(self.sum.load(Ordering::Relaxed));
+        let _ = writeln!(out, "{name}_sum {sum}\n{name}_count {count}");
+    }
+}
+
+pub fn record_cache_lookup(hit: bool) {
+    let counter = if hit {
+        &METRICS.cache_hits
+    } else {
+        &METRICS.cache_misses
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records a single request to upstream, including failed ones and retries.
+pub fn record_upstream_request(duration: Duration) {
+    METRICS.upstream_duration.observe(duration.as_secs_f64());
+}
+
+/// Records parsing a page, along with the events it had to skip.
+pub fn record_parse<T>(duration: Duration, result: &Result<T, ParseError>, skipped: &[ParseError]) {
+    METRICS.parse_duration.observe(duration.as_secs_f64());
+    for err in result.as_ref().err().into_iter().chain(skipped) {
+        METRICS.parse_failures[failure_kind(err)].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Records the number of events of a calendar fetched from upstream.
+pub fn record_events(count: usize) {
+    METRICS.events.observe(count as f64);
+}
+
+/// Serves the metrics at `/metrics` in the Prometheus text format.
+pub fn apply_routes(router: Router, cache: CalendarCache) -> Router {
+    router.route("/metrics", get(metrics_handler).with_state(cache))
+}
+
+/// Counts responses by status code.
+pub fn apply_middleware(router: Router) -> Router {
+    router.route_layer(middleware::from_fn(metrics_middleware))
+}
+
+async fn metrics_middleware(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let status = usize::from(response.status().as_u16());
+    if let Some(counter) = METRICS.responses.get(status.wrapping_sub(100)) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+    response
+}
+
+async fn metrics_handler(State(cache): State<CalendarCache>) -> Response {
+    let mut out = String::new();
+
+    out.push_str("# HELP rapla_responses_total Responses by status code.\n");
+    out.push_str("# TYPE rapla_responses_total counter\n");
+    for (idx, counter) in METRICS.responses.iter().enumerate() {
+        let count = counter.load(Ordering::Relaxed);
+        if count > 0 {
+            let _ = writeln!(
+                out,
+                "rapla_responses_total{{status=\"{}\"}} {count}",
+                idx + 100
+            );
+        }
+    }
+
+    out.push_str("# HELP rapla_cache_lookups_total Calendar lookups by whether they were answered from the cache.\n");
+    out.push_str("# TYPE rapla_cache_lookups_total counter\n");
+    let _ = writeln!(
+        out,
+        "rapla_cache_lookups_total{{result=\"hit\"}} {}\nrapla_cache_lookups_total{{result=\"miss\"}} {}",
+        METRICS.cache_hits.load(Ordering::Relaxed),
+        METRICS.cache_misses.load(Ordering::Relaxed),
+    );
+
+    let _ = writeln!(
+        out,
+        "# HELP rapla_cache_entries Calendars in the cache.\n# TYPE rapla_cache_entries gauge\nrapla_cache_entries {}",
+        cache.entry_count(),
+    );
+    let _ = writeln!(
+        out,
+        "# HELP rapla_cache_size_bytes Estimated size of the cache.\n# TYPE rapla_cache_size_bytes gauge\nrapla_cache_size_bytes {}",
+        cache.weighted_size(),
+    );
+
+    METRICS.upstream_duration.write(
+        &mut out,
+        "rapla_upstream_request_duration_seconds",
+        "Duration of requests to upstream, including failed ones and retries.",
+    );
+    METRICS.parse_duration.write(
+        &mut out,
+        "rapla_parse_duration_seconds",
+        "Duration of parsing an upstream page.",
+    );
+
+    out.push_str("# HELP rapla_parse_failures_total Parts of upstream pages that could not be parsed, including skipped events.\n");
+    out.push_str("# TYPE rapla_parse_failures_total counter\n");
+    for (kind, counter) in FAILURE_KINDS.iter().zip(&METRICS.parse_failures) {
+        let _ = writeln!(
+            out,
+            "rapla_parse_failures_total{{kind=\"{kind}\"}} {}",
+            counter.load(Ordering::Relaxed)
+        );
+    }
+
+    METRICS.events.write(
+        &mut out,
+        "rapla_calendar_events",
+        "Events per calendar fetched from upstream.",
+    );
+
+    (
+        [("content-type", "text/plain; version=0.0.4; charset=utf-8")],
+        out,
+    )
+        .into_response()
+}
@@ -1,52 +1,81 @@
+use std::collections::HashMap;
+use std::fmt;
 use std::ops::Not;
 use std::sync::OnceLock;
 
 use chrono::{Duration, NaiveDate, NaiveTime};
 use html_escape::decode_html_entities;
+use reqwest::Url;
 use scraper::{ElementRef, Html, Selector};
+use serde::Deserialize;
+use xxhash_rust::xxh3::xxh3_64;
 
 use crate::calendar::{Calendar, Event};
 
-trait InspectNone {
-    fn inspect_none(self, f: impl FnOnce()) -> Self;
+/// Describes which part of the Rapla markup could not be parsed.
+///
+/// Week indices count the `table.week_table` blocks of the page from zero,
+/// event positions are the row and column of the `td.week_block` cell within
+/// its week table.
+#[derive(Debug, Clone)]
+pub enum ParseError {
+    Calendar {
+        expected: &'static str,
+    },
+    Week {
+        week: usize,
+        expected: &'static str,
+        snippet: String,
+    },
+    Event {
+        week: usize,
+        row: usize,
+        column: usize,
+        expected: &'static str,
+        snippet: String,
+    },
+    /// One of several pages of a calendar that couldn't be fetched or parsed.
+    Page {
+        start: NaiveDate,
+        reason: String,
+    },
 }
 
-impl<T> InspectNone for Option<T> {
-    #[inline]
-    fn inspect_none(self, f: impl FnOnce()) -> Self {
-        self.or_else(|| {
-            f();
-            None
-        })
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Calendar { expected } => write!(f, "expected {expected}"),
+            Self::Week {
+                week,
+                expected,
+                snippet,
+            } => write!(f, "week {week}: expected {expected} in `{snippet}`"),
+            Self::Event {
+                week,
+                row,
+                column,
+                expected,
+                snippet,
+            } => write!(
+                f,
+                "week {week}, row {row}, column {column}: expected {expected} in `{snippet}`"
+            ),
+            Self::Page { start, reason } => write!(f, "page from {start}: {reason}"),
+        }
     }
 }
 
-macro_rules! loc {
-    () => {
-        concat!(file!(), ":", line!(), ":", column!())
-    };
-}
+impl std::error::Error for ParseError {}
 
-macro_rules! trace_none {
-    ($($arg:expr), *) => {
-        || {
-            if cfg!(debug_assertions) {
-                eprintln!(concat!("[trace] none at ", loc!()));
-                $(dbg!($arg);)*
-            }
-        }
-    };
-}
+/// Shortens raw HTML to something that fits into a log line or error message.
+fn snippet(html: &str) -> String {
+    const MAX_CHARS: usize = 200;
 
-macro_rules! trace_err {
-    ($($arg:expr), *) => {
-        |err| {
-            if cfg!(debug_assertions) {
-                eprintln!("[trace] err at {}: {err}", loc!());
-                $(dbg!($arg);)*
-            }
-        }
-    };
+    let collapsed = html.split_whitespace().collect::<Vec<_>>().join(" ");
+    match collapsed.char_indices().nth(MAX_CHARS) {
+        Some((idx, _)) => format!("{}...", &collapsed[..idx]),
+        None => collapsed,
+    }
 }
 
 macro_rules! select {
@@ -56,80 +85,153 @@ macro_rules! select {
     }};
 }
 
-pub fn parse_calendar(s: &str, mut start_year: i32) -> Option<Calendar> {
+/// Parses a Rapla week view page, `base` is the page's URL for resolving links.
+///
+/// In strict mode the first broken week or event fails the whole calendar.
+/// Otherwise broken weeks and events are skipped and recorded in
+/// [`Calendar::skipped`]. A page without week tables, or where nothing but
+/// broken weeks and events are left, is an error either way, as that usually
+/// means Rapla changed its markup.
+///
+/// `year` is the year of the first week's Monday, later weeks are in the next
+/// year once their month is before the previous week's.
+pub fn parse_calendar(
+    s: &str,
+    base: &Url,
+    mut year: i32,
+    strict: bool,
+) -> Result<Calendar, ParseError> {
     let html = Html::parse_document(s);
     let name = select!(html, "title")
         .next()
-        .inspect_none(trace_none!())?
+        .ok_or(ParseError::Calendar {
+            expected: "page title",
+        })?
         .inner_html()
         .trim()
         .to_string();
 
     let mut events = Vec::new();
-    for (idx, week_element) in select!(html, "div.calendar > table.week_table > tbody").enumerate()
-    {
-        let week_number_html = select!(week_element, "th.week_number")
-            .next()
-            .inspect_none(trace_none!())?
-            .inner_html();
-
-        let week_number = week_number_html
-            .split(' ')
-            .nth(1)
-            .inspect_none(trace_none!(&week_number_html))?
-            .parse::<usize>()
-            .inspect_err(trace_err!())
-            .ok()?;
-
-        if week_number == 1 && idx > 0 {
-            start_year += 1;
+    let mut skipped = Vec::new();
+    let mut skip = |err| {
+        if strict {
+            return Err(err);
         }
+        skipped.push(err);
+        Ok(())
+    };
 
-        let mut week_events = parse_week(week_element, start_year).inspect_none(trace_none!())?;
-        events.append(&mut week_events);
+    let mut weeks = select!(html, "div.calendar > table.week_table > tbody").peekable();
+    if weeks.peek().is_none() {
+        return Err(ParseError::Calendar {
+            expected: "week tables",
+        });
     }
 
-    Some(Calendar { name, events })
+    let mut last_month = None;
+    for (idx, week_element) in weeks.enumerate() {
+        let (header, day, month) = match parse_week_start(week_element, idx) {
+            Ok(start) => start,
+            Err(err) => {
+                skip(err)?;
+                continue;
+            }
+        };
+
+        // Weeks are in order, so going back in months means a new year.
+        if last_month.is_some_and(|last_month| month < last_month) {
+            year += 1;
+        }
+        last_month = Some(month);
+
+        let Some(monday) = NaiveDate::from_ymd_opt(year, month, day) else {
+            skip(ParseError::Week {
+                week: idx,
+                expected: "valid start date",
+                snippet: snippet(&header),
+            })?;
+            continue;
+        };
+
+        match parse_week(week_element, idx, base, monday, &name, &mut skip) {
+            Ok(mut week_events) => events.append(&mut week_events),
+            Err(err) => skip(err)?,
+        }
+    }
+
+    // An empty calendar is fine, one that only failed to parse isn't.
+    if events.is_empty() && !skipped.is_empty() {
+        return Err(skipped.swap_remove(0));
+    }
+
+    disambiguate_uids(&mut events);
+
+    Ok(Calendar {
+        name,
+        events,
+        skipped,
+    })
 }
 
-fn parse_week(element: ElementRef, start_year: i32) -> Option<Vec<Event>> {
+/// Returns the week header along with the day and month of the week's Monday.
+fn parse_week_start(element: ElementRef, week: usize) -> Result<(String, u32, u32), ParseError> {
+    let week_error = |expected, html: &str| ParseError::Week {
+        week,
+        expected,
+        snippet: snippet(html),
+    };
+
     let week_header = select!(element, "tr > td.week_header > nobr")
         .next()
-        .inspect_none(trace_none!())?
+        .ok_or_else(|| week_error("week header", &element.html()))?
         .inner_html();
 
     let mut day_month = week_header
         .split(' ')
         .nth(1)
-        .inspect_none(trace_none!())?
+        .ok_or_else(|| week_error("start date", &week_header))?
         .trim_end_matches('.')
         .split('.');
 
     let start_day = day_month
         .next()
-        .inspect_none(trace_none!())?
-        .parse::<u32>()
-        .inspect_err(trace_err!())
-        .ok()?;
+        .and_then(|day| day.parse::<u32>().ok())
+        .ok_or_else(|| week_error("start day", &week_header))?;
 
     let start_month = day_month
-        .next()?
-        .parse::<u32>()
-        .inspect_err(trace_err!())
-        .ok()?;
+        .next()
+        .and_then(|month| month.parse::<u32>().ok())
+        .ok_or_else(|| week_error("start month", &week_header))?;
 
-    let monday =
-        NaiveDate::from_ymd_opt(start_year, start_month, start_day).inspect_none(trace_none!())?;
+    Ok((week_header, start_day, start_month))
+}
 
+/// Broken events are handed to `skip`, which decides whether they abort the week.
+fn parse_week(
+    element: ElementRef,
+    week: usize,
+    base: &Url,
+    monday: NaiveDate,
+    calendar: &str,
+    skip: &mut impl FnMut(ParseError) -> Result<(), ParseError>,
+) -> Result<Vec<Event>, ParseError> {
     let mut events = Vec::new();
-    for row in select!(element, "tr").skip(1) {
+    let mut day_slots = HashMap::<i64, usize>::new();
+    for (row_idx, row) in select!(element, "tr").enumerate().skip(1) {
         let mut day_index = 0;
-        for column in select!(row, "td") {
-            let class = column
-                .value()
-                .classes()
-                .next()
-                .inspect_none(trace_none!())?;
+        for (column_idx, column) in select!(row, "td").enumerate() {
+            let event_error = |expected| ParseError::Event {
+                week,
+                row: row_idx,
+                column: column_idx,
+                expected,
+                snippet: snippet(&column.html()),
+            };
+
+            let Some(class) = column.value().classes().next() else {
+                skip(event_error("cell class"))?;
+                continue;
+            };
 
             if class.starts_with("week_separatorcell") {
                 day_index += 1;
@@ -139,29 +241,47 @@ fn parse_week(element: ElementRef, start_year: i32) -> Option<Vec<Event>> {
                 continue;
             }
 
-            let date = monday + Duration::try_days(day_index).inspect_none(trace_none!())?;
-            events.push(parse_event(column, date).inspect_none(trace_none!())?);
+            // Count broken events too, so skipping one doesn't shift the others' UIDs.
+            let slot = day_slots.entry(day_index).or_default();
+            *slot += 1;
+
+            let event = Duration::try_days(day_index)
+                .and_then(|offset| monday.checked_add_signed(offset))
+                .ok_or("valid day offset")
+                .and_then(|date| parse_event(column, date, base, calendar, *slot));
+
+            match event {
+                Ok(event) => events.push(event),
+                Err(expected) => skip(event_error(expected))?,
+            }
         }
     }
 
-    Some(events)
+    Ok(events)
 }
 
-fn parse_event(element: ElementRef, date: NaiveDate) -> Option<Event> {
+/// Returns what was expected but missing on failure, the caller knows where we are.
+fn parse_event(
+    element: ElementRef,
+    date: NaiveDate,
+    base: &Url,
+    calendar: &str,
+    slot: usize,
+) -> Result<Event, &'static str> {
     // Sometimes there is an extra <span class="link"> wrapper around the content we're after.
     // We pick last element to ensure we have the innermost matched element.
     let details = select!(element, ":is(a, span.link)")
         .last()
-        .inspect_none(trace_none!())?
+        .ok_or("event link")?
         .inner_html();
 
     let mut details_split = details.split("<br>");
 
-    let times_raw = details_split.next().inspect_none(trace_none!())?;
+    let times_raw = details_split.next().ok_or("event times")?;
     let mut times_raw_split = times_raw.split("&nbsp;-");
 
-    let start_time_raw = times_raw_split.next().inspect_none(trace_none!())?;
-    let end_time_raw = times_raw_split.next().inspect_none(trace_none!())?;
+    let start_time_raw = times_raw_split.next().ok_or("start time")?;
+    let end_time_raw = times_raw_split.next().ok_or("end time")?;
 
     // Some geniuses at DHBW find it a great idea to leave out the start and/or
     // end time to signify "full day" which is to be interpreted as "from 08:00
@@ -169,19 +289,15 @@ fn parse_event(element: ElementRef, date: NaiveDate) -> Option<Event> {
     let start = if start_time_raw.is_empty() {
         NaiveTime::from_hms_opt(8, 0, 0).unwrap()
     } else {
-        NaiveTime::parse_from_str(start_time_raw, "%H:%M")
-            .inspect_err(trace_err!())
-            .ok()?
+        NaiveTime::parse_from_str(start_time_raw, "%H:%M").map_err(|_| "start time as HH:MM")?
     };
     let end = if end_time_raw.is_empty() {
         NaiveTime::from_hms_opt(18, 0, 0).unwrap()
     } else {
-        NaiveTime::parse_from_str(end_time_raw, "%H:%M")
-            .inspect_err(trace_err!())
-            .ok()?
+        NaiveTime::parse_from_str(end_time_raw, "%H:%M").map_err(|_| "end time as HH:MM")?
     };
 
-    let title = details_split.next().inspect_none(trace_none!())?;
+    let title = details_split.next().ok_or("event title")?;
     let title = decode_html_entities(title).to_string();
 
     let resources = select!(element, "span.resource")
@@ -195,7 +311,32 @@ fn parse_event(element: ElementRef, date: NaiveDate) -> Option<Event> {
         .collect::<Vec<_>>();
     let organizer = persons.is_empty().not().then(|| persons.join(", "));
 
-    Some(Event {
+    // The tooltip looks like "<strong>Vorlesung</strong> ... <table class="infotable">",
+    // with one label/value row per reservation attribute.
+    let kind = select!(element, "span.tooltip strong")
+        .next()
+        .map(|kind| text(kind))
+        .filter(|kind| !kind.is_empty());
+
+    let remarks = infotable_values(element, REMARK_LABELS, "\n");
+    let course = infotable_values(element, COURSE_LABELS, ", ");
+
+    let url = select!(element, "a[href]")
+        .filter_map(|link| link.value().attr("href"))
+        .find(|href| !href.starts_with('#') && !href.starts_with("javascript:"))
+        .and_then(|href| base.join(href).ok())
+        .map(String::from);
+
+    let uid = match reservation_id(element) {
+        Some(id) => format!("rapla-{id}-{}", date.format("%Y%m%d")),
+        None => {
+            let identity = format!("{calendar}\n{date}\n{slot}\n{}", resources.join("\n"));
+            format!("rapla-{:016x}", xxh3_64(identity.as_bytes()))
+        }
+    };
+
+    Ok(Event {
+        uid,
         date,
         start,
         end,
@@ -203,5 +344,428 @@ fn parse_event(element: ElementRef, date: NaiveDate) -> Option<Event> {
         location,
         organizer,
         description,
+        kind,
+        course,
+        remarks,
+        url,
     })
 }
+
+/// Infotable rows whose values end up in [`Event::remarks`].
+const REMARK_LABELS: &[&str] = &["Bemerkung", "Kommentar"];
+
+/// Infotable rows whose values end up in [`Event::course`].
+const COURSE_LABELS: &[&str] = &["Kurs", "Kurse", "Studiengang"];
+
+/// The non-empty values of the tooltip's infotable rows labeled with any of `labels`.
+fn infotable_values(element: ElementRef, labels: &[&str], separator: &str) -> Option<String> {
+    let values = select!(element, "span.tooltip table.infotable tr")
+        .filter_map(|row| {
+            let label = text(select!(row, "td.label").next()?);
+            let value = text(select!(row, "td.value").next()?);
+            let label = label.trim_end_matches(':');
+            (labels.contains(&label) && !value.is_empty()).then_some(value)
+        })
+        .collect::<Vec<_>>();
+    values.is_empty().not().then(|| values.join(separator))
+}
+
+fn text(element: ElementRef) -> String {
+    element
+        .text()
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[derive(Deserialize)]
+struct EventLinkQuery {
+    id: Option<String>,
+}
+
+/// Extracts Rapla's reservation id from the event link, if there is one.
+///
+/// A reservation covers every appointment of e.g. a lecture series, so this
+/// only identifies an event together with its date.
+fn reservation_id(element: ElementRef) -> Option<String> {
+    select!(element, "a[href]")
+        .filter_map(|link| link.value().attr("href")?.split_once('?'))
+        .filter_map(|(_, query)| serde_urlencoded::from_str::<EventLinkQuery>(query).ok()?.id)
+        .find(|id| !id.is_empty())
+}
+
+/// Appends a counter to UIDs that occur more than once, in order of appearance.
+fn disambiguate_uids(events: &mut [Event]) {
+    let mut seen = HashMap::<String, usize>::new();
+    for event in events {
+        let count = seen.entry(event.uid.clone()).or_default();
+        *count += 1;
+        if *count > 1 {
+            event.uid = format!("{}-{count}", event.uid);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> Url {
+        Url::parse("https://rapla.dhbw.de/rapla/calendar").unwrap()
+    }
+
+    fn page(weeks: &[String]) -> String {
+        format!(
+            r#"<html><head><title>TINF22B</title></head><body><div class="calendar">{}</div></body></html>"#,
+            weeks.concat()
+        )
+    }
+
+    fn week(header: &str, rows: &[String]) -> String {
+        let rows = rows
+            .iter()
+            .map(|row| format!("<tr>{row}</tr>"))
+            .collect::<String>();
+        format!(
+            r#"<table class="week_table"><tbody><tr><th class="week_number">KW 1</th><td class="week_header"><nobr>{header}</nobr></td></tr>{rows}</tbody></table>"#
+        )
+    }
+
+    fn event(times: &str, title: &str) -> String {
+        format!(
+            r#"<td class="week_block"><a href="/rapla/eventinfo?id=abc">{times}<br>{title}<br></a><span class="resource">A1</span></td>"#
+        )
+    }
+
+    fn lecture() -> String {
+        event("08:00&nbsp;-10:00", "Mathe")
+    }
+
+    #[test]
+    fn parses_events() {
+        let html = page(&[week(
+            "Mo 30.09.",
+            &[format!(
+                r#"<td class="week_times"></td>{}<td class="week_separatorcell"></td>{}"#,
+                lecture(),
+                event("&nbsp;-", "Projekt"),
+            )],
+        )]);
+
+        let calendar = parse_calendar(&html, &base(), 2024, true).unwrap();
+        assert_eq!(calendar.name, "TINF22B");
+        assert!(calendar.skipped.is_empty());
+
+        let [mathe, projekt] = calendar.events.as_slice() else {
+            panic!("expected two events, got {:?}", calendar.events);
+        };
+        assert_eq!(mathe.title, "Mathe");
+        assert_eq!(mathe.date, NaiveDate::from_ymd_opt(2024, 9, 30).unwrap());
+        assert_eq!(mathe.start, NaiveTime::from_hms_opt(8, 0, 0).unwrap());
+        assert_eq!(mathe.location.as_deref(), Some("A1"));
+        assert_eq!(projekt.date, NaiveDate::from_ymd_opt(2024, 10, 1).unwrap());
+        assert_eq!(projekt.start, NaiveTime::from_hms_opt(8, 0, 0).unwrap());
+        assert_eq!(projekt.end, NaiveTime::from_hms_opt(18, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn parses_tooltip() {
+        let html = page(&[week(
+            "Mo 30.09.",
+            &[r#"<td class="week_block"><a href="/rapla/eventinfo?id=abc&amp;lang=de">08:00&nbsp;-10:00<br>Mathe<br><span class="tooltip"><strong>Klausur</strong><table class="infotable"><tr><td class="label">Titel:</td><td class="value">Mathe</td></tr><tr><td class="label">Kurs:</td><td class="value">TINF22B1</td></tr><tr><td class="label">Kurs:</td><td class="value">TINF22B2</td></tr><tr><td class="label">Bemerkung:</td><td class="value">Bitte   Laptop mitbringen</td></tr><tr><td class="label">Kommentar:</td><td class="value">Raum &amp; Zeit vorläufig</td></tr><tr><td class="label">Bemerkung:</td><td class="value"> </td></tr></table></span></a></td>"#.to_string()],
+        )]);
+
+        let calendar = parse_calendar(&html, &base(), 2024, true).unwrap();
+        let [event] = calendar.events.as_slice() else {
+            panic!("expected one event, got {:?}", calendar.events);
+        };
+        assert_eq!(event.kind.as_deref(), Some("Klausur"));
+        assert_eq!(event.course.as_deref(), Some("TINF22B1, TINF22B2"));
+        assert_eq!(
+            event.remarks.as_deref(),
+            Some("Bitte Laptop mitbringen\nRaum & Zeit vorläufig")
+        );
+        assert_eq!(
+            event.url.as_deref(),
+            Some("https://rapla.dhbw.de/rapla/eventinfo?id=abc&lang=de")
+        );
+    }
+
+    #[test]
+    fn leaves_out_missing_tooltip() {
+        let html = page(&[week("Mo 30.09.", &[lecture()])]);
+        let calendar = parse_calendar(&html, &base(), 2024, true).unwrap();
+        let event = &calendar.events[0];
+        assert_eq!(event.kind, None);
+        assert_eq!(event.course, None);
+        assert_eq!(event.remarks, None);
+    }
+
+    #[test]
+    fn reports_page_without_title() {
+        let err = parse_calendar("<html></html>", &base(), 2024, false).unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::Calendar {
+                expected: "page title"
+            }
+        ));
+    }
+
+    #[test]
+    fn reports_week_position() {
+        let html = page(&[week("Mo 30.09.", &[]), week("Mo", &[])]);
+
+        let err = parse_calendar(&html, &base(), 2024, true).unwrap_err();
+        let ParseError::Week {
+            week,
+            expected,
+            snippet,
+        } = &err
+        else {
+            panic!("expected week error, got {err:?}");
+        };
+        assert_eq!(*week, 1);
+        assert_eq!(*expected, "start date");
+        assert_eq!(snippet, "Mo");
+        assert_eq!(err.to_string(), "week 1: expected start date in `Mo`");
+    }
+
+    #[test]
+    fn reports_event_position() {
+        let html = page(&[week(
+            "Mo 30.09.",
+            &[
+                lecture(),
+                format!(
+                    "{}<td>\n  unexpected\n  cell\n</td>",
+                    event("08:00&nbsp;-10:00", "Mathe")
+                ),
+            ],
+        )]);
+
+        let err = parse_calendar(&html, &base(), 2024, true).unwrap_err();
+        let ParseError::Event {
+            week,
+            row,
+            column,
+            expected,
+            snippet,
+        } = &err
+        else {
+            panic!("expected event error, got {err:?}");
+        };
+        assert_eq!((*week, *row, *column), (0, 2, 1));
+        assert_eq!(*expected, "cell class");
+        assert_eq!(snippet, "<td> unexpected cell </td>");
+    }
+
+    #[test]
+    fn reports_what_is_missing_from_event() {
+        let html = page(&[week("Mo 30.09.", &[event("8 Uhr&nbsp;-10:00", "Mathe")])]);
+
+        let err = parse_calendar(&html, &base(), 2024, true).unwrap_err();
+        assert!(
+            matches!(
+                &err,
+                ParseError::Event {
+                    week: 0,
+                    row: 1,
+                    column: 0,
+                    expected: "start time as HH:MM",
+                    snippet,
+                } if snippet.contains("8 Uhr")
+            ),
+            "unexpected error {err:?}"
+        );
+    }
+
+    fn dates(html: &str, year: i32) -> Vec<NaiveDate> {
+        parse_calendar(html, &base(), year, true)
+            .unwrap()
+            .events
+            .into_iter()
+            .map(|event| event.date)
+            .collect()
+    }
+
+    #[test]
+    fn rolls_over_year_when_month_goes_back() {
+        // The Monday of ISO week 1 of 2026 is still in 2025.
+        let html = page(&[
+            week("Mo 22.12.", &[lecture()]),
+            week("Mo 29.12.", &[lecture()]),
+            week("Mo 05.01.", &[lecture()]),
+        ]);
+
+        assert_eq!(
+            dates(&html, 2025),
+            [
+                NaiveDate::from_ymd_opt(2025, 12, 22).unwrap(),
+                NaiveDate::from_ymd_opt(2025, 12, 29).unwrap(),
+                NaiveDate::from_ymd_opt(2026, 1, 5).unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_page_without_weeks() {
+        let html = page(&[]);
+        let err = parse_calendar(&html, &base(), 2024, false).unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::Calendar {
+                expected: "week tables"
+            }
+        ));
+    }
+
+    #[test]
+    fn skips_broken_events() {
+        let html = page(&[week("Mo 30.09.", &[format!("{}<td></td>", lecture())])]);
+
+        let calendar = parse_calendar(&html, &base(), 2024, false).unwrap();
+        assert_eq!(calendar.events.len(), 1);
+        assert!(matches!(
+            calendar.skipped.as_slice(),
+            [ParseError::Event {
+                expected: "cell class",
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn rejects_calendar_without_readable_events() {
+        let html = page(&[
+            week("Mo 30.09.", &[event("8 Uhr&nbsp;-10:00", "Mathe")]),
+            week("Mo", &[lecture()]),
+        ]);
+
+        let err = parse_calendar(&html, &base(), 2024, false).unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::Event {
+                expected: "start time as HH:MM",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn accepts_empty_calendar() {
+        let html = page(&[week("Mo 30.09.", &[])]);
+        let calendar = parse_calendar(&html, &base(), 2024, false).unwrap();
+        assert!(calendar.events.is_empty());
+        assert!(calendar.skipped.is_empty());
+    }
+
+    fn uids(html: &str) -> Vec<String> {
+        parse_calendar(html, &base(), 2024, true)
+            .unwrap()
+            .events
+            .into_iter()
+            .map(|event| event.uid)
+            .collect()
+    }
+
+    #[test]
+    fn derives_uid_from_reservation_and_date() {
+        let html = page(&[week(
+            "Mo 30.09.",
+            &[format!(
+                r#"{}<td class="week_separatorcell"></td>{}"#,
+                lecture(),
+                lecture()
+            )],
+        )]);
+
+        assert_eq!(uids(&html), ["rapla-abc-20240930", "rapla-abc-20241001"]);
+    }
+
+    #[test]
+    fn derives_stable_uid_without_reservation() {
+        let unlinked = |times, title| {
+            format!(
+                r#"<td class="week_block"><span class="link">{times}<br>{title}<br></span><span class="resource">A1</span></td>"#
+            )
+        };
+        let calendar = |times, title| {
+            page(&[week(
+                "Mo 30.09.",
+                &[format!(
+                    "{}{}",
+                    unlinked("08:00&nbsp;-10:00", "Mathe"),
+                    unlinked(times, title)
+                )],
+            )])
+        };
+
+        let original = uids(&calendar("10:15&nbsp;-12:00", "Physik"));
+        let [first, second] = original.as_slice() else {
+            panic!("expected two events, got {original:?}");
+        };
+        assert!(first.starts_with("rapla-") && first.len() == "rapla-".len() + 16);
+        assert_ne!(first, second);
+
+        // Moving or renaming an event keeps its UID, as long as it stays the same slot of its day.
+        assert_eq!(uids(&calendar("13:00&nbsp;-14:30", "Chemie")), original);
+    }
+
+    #[test]
+    fn broken_events_keep_their_slot() {
+        let unlinked = |times| {
+            format!(
+                r#"<td class="week_block"><span class="link">{times}<br>Mathe<br></span><span class="resource">A1</span></td>"#
+            )
+        };
+        let calendar = |first| {
+            page(&[week(
+                "Mo 30.09.",
+                &[format!(
+                    "{}{}",
+                    unlinked(first),
+                    unlinked("10:15&nbsp;-12:00")
+                )],
+            )])
+        };
+
+        let all = uids(&calendar("08:00&nbsp;-10:00"));
+        let lenient = parse_calendar(&calendar("8 Uhr&nbsp;-10:00"), &base(), 2024, false).unwrap();
+        assert_eq!(lenient.events.len(), 1);
+        assert_eq!(lenient.events[0].uid, all[1]);
+    }
+
+    #[test]
+    fn disambiguates_duplicate_uids() {
+        let html = page(&[week("Mo 30.09.", &[lecture(), lecture(), lecture()])]);
+        let mut events = parse_calendar(&html, &base(), 2024, true).unwrap().events;
+        assert_eq!(
+            events.iter().map(|event| &event.uid).collect::<Vec<_>>(),
+            [
+                "rapla-abc-20240930",
+                "rapla-abc-20240930-2",
+                "rapla-abc-20240930-3"
+            ]
+        );
+
+        events[0].uid = "a".into();
+        events[1].uid = "b".into();
+        events[2].uid = "a".into();
+        disambiguate_uids(&mut events);
+        assert_eq!(
+            events.iter().map(|event| &event.uid).collect::<Vec<_>>(),
+            ["a", "b", "a-2"]
+        );
+    }
+
+    #[test]
+    fn shortens_snippets() {
+        assert_eq!(snippet("  <td>\n\t a  b </td> "), "<td> a b </td>");
+
+        let long = "ä".repeat(250);
+        let short = snippet(&long);
+        assert_eq!(short, format!("{}...", "ä".repeat(200)));
+    }
+}
@@ -1,27 +1,57 @@
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
 use std::fmt;
+use std::hash::BuildHasher;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use axum::extract::State;
-use axum::http::StatusCode;
+use axum::http::header::RETRY_AFTER;
+use axum::http::{HeaderMap, StatusCode, Uri};
 use axum::response::{IntoResponse, Response};
 use axum::routing::get;
 use axum::{Extension, Router};
+use chrono::Datelike;
+use tracing::Instrument;
 
+use crate::cache::{CacheLookup, CalendarCache};
 use crate::calendar::Calendar;
-use crate::resolver::UpstreamUrlExtension;
+use crate::format::Format;
+use crate::parser::ParseError;
+use crate::resolver::{UpstreamPage, UpstreamUrlExtension};
 
 pub enum Error {
     Request(reqwest::Error),
-    Parse,
+    Timeout(reqwest::Error),
+    /// Upstream failed repeatedly, so requests to it are held back for a while.
+    CircuitOpen {
+        host: String,
+        retry_after: Duration,
+    },
+    Parse(ParseError),
 }
 
+/// Attached to error responses so the logging middleware can report the cause.
+#[derive(Debug, Clone)]
+pub struct ErrorDetails(pub String);
+
+/// Attached to calendar responses so the logging middleware can report skipped events.
+#[derive(Debug, Clone)]
+pub struct SkippedEvents(pub Vec<String>);
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let message = match &self {
-            Self::Request(err) if err.is_status() => "upstream returned unexpected status code",
-            Self::Request(_) => "can't connect to upstream",
-            Self::Parse => "can't parse calendar",
-        };
-        write!(f, "{message}")
+        match &self {
+            Self::Request(err) if err.is_status() => {
+                write!(f, "upstream returned unexpected status code")
+            }
+            Self::Request(_) => write!(f, "can't connect to upstream"),
+            Self::Timeout(_) => write!(f, "upstream timed out"),
+            Self::CircuitOpen { host, .. } => {
+                write!(f, "{host} is failing, not asking it again for now")
+            }
+            Self::Parse(err) => write!(f, "can't parse calendar: {err}"),
+        }
     }
 }
 
@@ -34,72 +64,602 @@ impl fmt::Debug for Error {
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
-            Self::Request(err) => Some(err),
-            Self::Parse => None,
+            Self::Request(err) | Self::Timeout(err) => Some(err),
+            Self::CircuitOpen { .. } => None,
+            Self::Parse(err) => Some(err),
         }
     }
 }
 
 impl From<reqwest::Error> for Error {
     fn from(value: reqwest::Error) -> Self {
-        Self::Request(value)
+        if value.is_timeout() {
+            Self::Timeout(value)
+        } else {
+            Self::Request(value)
+        }
     }
 }
 
-impl IntoResponse for Error {
-    fn into_response(self) -> Response {
-        let status = match &self {
+impl From<ParseError> for Error {
+    fn from(value: ParseError) -> Self {
+        Self::Parse(value)
+    }
+}
+
+impl Error {
+    /// Whether the error is likely to go away on its own, i.e. upstream is
+    /// unreachable or broken, or serves markup we can't parse right now.
+    pub fn is_temporary(&self) -> bool {
+        match self {
+            Self::Request(err) => err.status().is_none_or(|status| status.is_server_error()),
+            Self::Timeout(_) | Self::CircuitOpen { .. } | Self::Parse(_) => true,
+        }
+    }
+
+    /// Whether asking upstream again might succeed. Unlike [`Self::is_temporary`],
+    /// this excludes markup we can't parse, which won't change within seconds.
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::Request(err) => err.status().is_none_or(|status| {
+                status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+            }),
+            Self::Timeout(_) => true,
+            Self::CircuitOpen { .. } | Self::Parse(_) => false,
+        }
+    }
+
+    pub fn to_response(&self) -> Response {
+        let status = match self {
             Self::Request(err) if err.is_status() => {
                 err.status().expect("error status should be set")
             } // Propagate whatever issue they're having.
             Self::Request(_) => StatusCode::BAD_GATEWAY,
-            Self::Parse => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            Self::CircuitOpen { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Parse(_) => StatusCode::INTERNAL_SERVER_ERROR,
         };
 
-        (
+        let message = self.to_string();
+        let mut response = (
             status,
             [("content-type", "text/plain")],
-            format!("Error: {self}"),
+            Extension(ErrorDetails(message.clone())),
+            format!("Error: {message}"),
         )
-            .into_response()
+            .into_response();
+
+        if let Self::CircuitOpen { retry_after, .. } = self {
+            // Round up, `Retry-After: 0` would invite clients to retry right away.
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response.headers_mut().insert(RETRY_AFTER, seconds.into());
+        }
+
+        response
     }
 }
 
-impl IntoResponse for Calendar {
-    fn into_response(self) -> axum::response::Response {
-        (
-            [("content-type", "text/calendar")],
-            self.to_ics().to_string(),
-        )
-            .into_response()
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        self.to_response()
+    }
+}
+
+pub struct UpstreamConfig {
+    /// How long establishing a connection to upstream may take.
+    pub connect_timeout: Duration,
+    /// How long a single request to upstream may take in total, including the body.
+    pub timeout: Duration,
+    /// How often a failed request is repeated before giving up.
+    pub retries: u32,
+    /// How many consecutive failures open the circuit breaker of a host.
+    pub breaker_threshold: u32,
+    /// How long an open circuit breaker holds back requests to its host.
+    pub breaker_cooldown: Duration,
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            timeout: Duration::from_secs(30),
+            retries: 2,
+            breaker_threshold: 5,
+            breaker_cooldown: Duration::from_secs(60),
+        }
+    }
+}
+
+/// HTTP client for upstream that applies timeouts, retries and circuit breaking.
+#[derive(Clone)]
+pub struct UpstreamClient {
+    client: reqwest::Client,
+    config: Arc<UpstreamConfig>,
+    breaker: CircuitBreaker,
+}
+
+impl UpstreamClient {
+    pub fn new(config: UpstreamConfig) -> Self {
+        const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+        let client = reqwest::Client::builder()
+            .user_agent(USER_AGENT)
+            .connect_timeout(config.connect_timeout)
+            .timeout(config.timeout)
+            .build()
+            .expect("reqwest client should build");
+
+        Self {
+            client,
+            breaker: CircuitBreaker::new(config.breaker_threshold, config.breaker_cooldown),
+            config: Arc::new(config),
+        }
+    }
+
+    /// Checks whether `host` answers at all, with a single request that
+    /// doesn't count towards its circuit breaker.
+    pub async fn probe(&self, host: &str) -> Result<(), Error> {
+        self.breaker.peek(host)?;
+        self.client.get(format!("https://{host}/")).send().await?;
+        Ok(())
+    }
+
+    /// Fetches `url` and returns its body, retrying temporary failures.
+    async fn get(&self, url: &str) -> Result<(reqwest::Url, String), Error> {
+        // Doubles with every attempt, but only up to a limit.
+        const BACKOFF: Duration = Duration::from_millis(500);
+        const MAX_BACKOFF: Duration = Duration::from_secs(8);
+
+        let mut attempt = 0;
+        loop {
+            let result = self.get_once(url, attempt).await;
+            match result {
+                Err(err) if err.is_retryable() && attempt < self.config.retries => {
+                    let backoff = BACKOFF
+                        .saturating_mul(1 << attempt.min(16))
+                        .min(MAX_BACKOFF);
+                    tracing::warn!(%err, attempt, "retrying upstream request");
+                    tokio::time::sleep(jitter(backoff)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // The URL holds the calendar's secrets, so only the host is recorded.
+    #[tracing::instrument(
+        name = "upstream",
+        skip(self, url),
+        fields(host, status, otel.kind = "client")
+    )]
+    async fn get_once(&self, url: &str, attempt: u32) -> Result<(reqwest::Url, String), Error> {
+        let mut request = self.client.get(url).build()?;
+        crate::telemetry::propagate_trace(request.headers_mut());
+        let host = request.url().host_str().unwrap_or_default().to_string();
+        tracing::Span::current().record("host", &host);
+        self.breaker.check(&host)?;
+
+        let start = Instant::now();
+        let result: Result<_, Error> = async {
+            let response = self.client.execute(request).await?;
+            tracing::Span::current().record("status", response.status().as_u16());
+            let response = response.error_for_status()?;
+            let base = response.url().clone();
+            Ok((base, response.text().await?))
+        }
+        .await;
+        let duration = start.elapsed();
+        crate::metrics::record_upstream_request(duration);
+
+        match &result {
+            Ok(_) => tracing::debug!(?duration, "upstream responded"),
+            Err(err) => tracing::debug!(?duration, %err, "upstream request failed"),
+        }
+        match &result {
+            Err(err) if err.is_retryable() => self.breaker.record_failure(&host),
+            _ => self.breaker.record_success(&host),
+        }
+        result
+    }
+}
+
+/// A random duration between half of `duration` and `duration`, so that
+/// requests failing together don't retry together.
+fn jitter(duration: Duration) -> Duration {
+    let random = RandomState::new().hash_one(Instant::now());
+    duration.mul_f64(0.5 + (random % 1000) as f64 / 2000.0)
+}
+
+/// Tracks consecutive failures per host. Once a host failed `threshold` times
+/// in a row, requests to it fail immediately until `cooldown` has passed. The
+/// next request after that is let through alone, while the others keep
+/// failing, and decides whether the host stays blocked for another `cooldown`.
+#[derive(Clone)]
+struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    hosts: Arc<Mutex<HashMap<String, HostState>>>,
+}
+
+#[derive(Default)]
+struct HostState {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            hosts: Arc::default(),
+        }
+    }
+
+    /// Fails while requests to `host` are held back. A request let through
+    /// after the cooldown has to record its outcome.
+    fn check(&self, host: &str) -> Result<(), Error> {
+        self.check_state(host, true)
+    }
+
+    /// Like [`Self::check`], but doesn't take the place of the request that
+    /// tries the host again after the cooldown.
+    fn peek(&self, host: &str) -> Result<(), Error> {
+        self.check_state(host, false)
+    }
+
+    fn check_state(&self, host: &str, try_again: bool) -> Result<(), Error> {
+        let mut hosts = self
+            .hosts
+            .lock()
+            .expect("breaker lock shouldn't be poisoned");
+        let Some(state) = hosts.get_mut(host) else {
+            return Ok(());
+        };
+        let Some(open_until) = state.open_until else {
+            return Ok(());
+        };
+
+        let now = Instant::now();
+        let retry_after = open_until.saturating_duration_since(now);
+        if !retry_after.is_zero() {
+            return Err(Error::CircuitOpen {
+                host: host.to_string(),
+                retry_after,
+            });
+        }
+
+        // Hold back everyone else until this request tells whether the host is back.
+        if try_again {
+            state.open_until = Some(now + self.cooldown);
+        }
+        Ok(())
     }
+
+    fn record_success(&self, host: &str) {
+        let mut hosts = self
+            .hosts
+            .lock()
+            .expect("breaker lock shouldn't be poisoned");
+        hosts.remove(host);
+    }
+
+    fn record_failure(&self, host: &str) {
+        // A threshold of 0 disables the breaker.
+        if self.threshold == 0 {
+            return;
+        }
+
+        let mut hosts = self
+            .hosts
+            .lock()
+            .expect("breaker lock shouldn't be poisoned");
+        let state = hosts.entry(host.to_string()).or_default();
+        state.failures += 1;
+        if state.failures >= self.threshold {
+            tracing::warn!(
+                host,
+                failures = state.failures,
+                "holding back requests to upstream"
+            );
+            state.open_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+}
+
+/// Fetches calendars from upstream through the cache.
+#[derive(Clone)]
+pub struct Proxy {
+    client: UpstreamClient,
+    cache: CalendarCache,
 }
 
-pub fn build_client() -> reqwest::Client {
-    const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
-    reqwest::Client::builder()
-        .user_agent(USER_AGENT)
-        .build()
-        .expect("reqwest client should build")
+impl Proxy {
+    pub fn new(client: UpstreamClient, cache: CalendarCache) -> Self {
+        Self { client, cache }
+    }
+
+    pub async fn fetch(&self, upstream: &UpstreamUrlExtension) -> CacheLookup {
+        let client = self.client.clone();
+        let fetch_upstream = upstream.clone();
+        self.cache
+            .get(upstream.cache_key(), async move {
+                handle(&client, &fetch_upstream).await
+            })
+            .await
+    }
 }
 
-pub fn apply_routes(router: Router) -> Router {
-    router.route("/{*path}", get(request_handler).with_state(build_client()))
+pub fn apply_routes(router: Router, proxy: Proxy) -> Router {
+    router.route("/{*path}", get(request_handler).with_state(proxy))
 }
 
 async fn request_handler(
-    State(client): State<reqwest::Client>,
+    State(proxy): State<Proxy>,
     Extension(upstream): Extension<UpstreamUrlExtension>,
-) -> Result<Response, Error> {
-    Ok(handle(&client, upstream).await?.into_response())
+    uri: Uri,
+    headers: HeaderMap,
+) -> Response {
+    let lookup = proxy.fetch(&upstream).await;
+
+    let mut response = match &lookup.result {
+        Ok(calendar) => {
+            let mut calendar = Calendar::clone(calendar);
+            upstream.apply(&mut calendar);
+            Format::negotiate(upstream.format, &headers).render(&calendar, &uri, &headers)
+        }
+        Err(err) => err.to_response(),
+    };
+
+    lookup.freshness.apply_headers(&mut response);
+    response
 }
 
+#[tracing::instrument(name = "fetch", skip_all, fields(pages = upstream.pages.len()))]
 pub async fn handle(
-    client: &reqwest::Client,
-    upstream: UpstreamUrlExtension,
+    client: &UpstreamClient,
+    upstream: &UpstreamUrlExtension,
 ) -> Result<Calendar, Error> {
-    let request = client.get(&upstream.url).build()?;
-    let response = client.execute(request).await?.error_for_status()?;
-    let html = response.text().await?;
-    crate::parser::parse_calendar(&html, upstream.start_year).ok_or(Error::Parse)
+    // Hosts that serve one week per page would get hammered by requesting all at once.
+    const CONCURRENT_PAGES: usize = 4;
+
+    let mut calendar: Option<Calendar> = None;
+    let mut failed = Vec::new();
+    for pages in upstream.pages.chunks(CONCURRENT_PAGES) {
+        let handles = pages
+            .iter()
+            .map(|page| {
+                let client = client.clone();
+                let page = page.clone();
+                let strict = upstream.strict;
+                tokio::spawn(
+                    async move { (page.start, handle_page(&client, &page, strict).await) }
+                        .in_current_span(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        for handle in handles {
+            let page = match handle.await.expect("page request panicked") {
+                (_, Ok(page)) => page,
+                // Unless strict, a broken week only leaves out that week.
+                (start, Err(err)) if !upstream.strict => {
+                    failed.push((start, err));
+                    continue;
+                }
+                (_, Err(err)) => return Err(err),
+            };
+            match &mut calendar {
+                Some(calendar) => {
+                    calendar.events.extend(page.events);
+                    calendar.skipped.extend(page.skipped);
+                }
+                None => calendar = Some(page),
+            }
+        }
+    }
+
+    let Some(mut calendar) = calendar else {
+        let (_, err) = failed
+            .into_iter()
+            .next()
+            .expect("upstream should have at least one page");
+        return Err(err);
+    };
+    calendar
+        .skipped
+        .extend(failed.into_iter().map(|(start, err)| ParseError::Page {
+            start,
+            reason: err.to_string(),
+        }));
+
+    crate::metrics::record_events(calendar.events.len());
+    Ok(calendar)
+}
+
+async fn handle_page(
+    client: &UpstreamClient,
+    page: &UpstreamPage,
+    strict: bool,
+) -> Result<Calendar, Error> {
+    let (base, html) = client.get(&page.url).await?;
+
+    let span = tracing::info_span!("parse");
+    let start = Instant::now();
+    let result =
+        span.in_scope(|| crate::parser::parse_calendar(&html, &base, page.start.year(), strict));
+    let duration = start.elapsed();
+    let skipped = result
+        .as_ref()
+        .map_or(&[][..], |calendar| &calendar.skipped);
+    crate::metrics::record_parse(duration, &result, skipped);
+
+    span.in_scope(|| match &result {
+        Ok(calendar) => tracing::debug!(
+            ?duration,
+            events = calendar.events.len(),
+            skipped = skipped.len(),
+            "parsed calendar"
+        ),
+        Err(err) => tracing::warn!(?duration, %err, "can't parse calendar"),
+    });
+
+    Ok(result?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use axum::extract::Query;
+    use axum::response::Html;
+    use chrono::{Days, NaiveDate};
+
+    use super::*;
+    use crate::filter::EventFilter;
+
+    const HOST: &str = "rapla.dhbw.de";
+    const COOLDOWN: Duration = Duration::from_millis(50);
+
+    fn open_breaker() -> CircuitBreaker {
+        let breaker = CircuitBreaker::new(2, COOLDOWN);
+        breaker.record_failure(HOST);
+        assert!(breaker.check(HOST).is_ok());
+        breaker.record_failure(HOST);
+        breaker
+    }
+
+    #[test]
+    fn opens_after_threshold() {
+        let breaker = open_breaker();
+        assert!(matches!(
+            breaker.check(HOST),
+            Err(Error::CircuitOpen { retry_after, .. }) if retry_after <= COOLDOWN
+        ));
+        assert!(breaker.check("rapla-ravensburg.dhbw.de").is_ok());
+    }
+
+    #[test]
+    fn lets_one_request_through_after_cooldown() {
+        let breaker = open_breaker();
+        std::thread::sleep(COOLDOWN);
+
+        assert!(breaker.peek(HOST).is_ok());
+        assert!(breaker.check(HOST).is_ok());
+        assert!(breaker.check(HOST).is_err());
+        assert!(breaker.peek(HOST).is_err());
+
+        breaker.record_success(HOST);
+        assert!(breaker.check(HOST).is_ok());
+        assert!(breaker.check(HOST).is_ok());
+    }
+
+    #[test]
+    fn reopens_when_retry_fails() {
+        let breaker = open_breaker();
+        std::thread::sleep(COOLDOWN);
+
+        assert!(breaker.check(HOST).is_ok());
+        breaker.record_failure(HOST);
+        assert!(matches!(
+            breaker.check(HOST),
+            Err(Error::CircuitOpen { retry_after, .. }) if retry_after > COOLDOWN / 2
+        ));
+    }
+
+    #[test]
+    fn threshold_of_zero_never_opens() {
+        let breaker = CircuitBreaker::new(0, COOLDOWN);
+        for _ in 0..10 {
+            breaker.record_failure(HOST);
+        }
+        assert!(breaker.check(HOST).is_ok());
+    }
+
+    #[derive(serde::Deserialize)]
+    struct WeekQuery {
+        day: u32,
+        month: u32,
+    }
+
+    /// Stands in for a Rapla host serving one week per page, with a broken
+    /// page for the week of October 7th.
+    async fn weekly_stand_in() -> SocketAddr {
+        let app = Router::new().route(
+            "/rapla/calendar",
+            get(|Query(WeekQuery { day, month }): Query<WeekQuery>| async move {
+                if (day, month) == (7, 10) {
+                    return Html("<html><body>Wartungsarbeiten</body></html>".to_string());
+                }
+                Html(format!(
+                    r#"<html><head><title>TINF22B</title></head><body><div class="calendar"><table class="week_table"><tbody><tr><th class="week_number">KW 1</th><td class="week_header"><nobr>Mo {day:02}.{month:02}.</nobr></td></tr><tr><td class="week_block"><a href="/rapla/eventinfo?id={day}">08:00&nbsp;-10:00<br>Mathe<br></a></td></tr></tbody></table></div></body></html>"#
+                ))
+            }),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        address
+    }
+
+    async fn fetch_weeks(days: &[u32], strict: bool) -> Result<Calendar, Error> {
+        let address = weekly_stand_in().await;
+        let date = |day| NaiveDate::from_ymd_opt(2024, 10, day).unwrap();
+        let upstream = UpstreamUrlExtension {
+            url: format!("http://{address}/rapla/calendar"),
+            pages: days
+                .iter()
+                .map(|&day| UpstreamPage {
+                    url: format!("http://{address}/rapla/calendar?day={day}&month=10&year=2024"),
+                    start: date(day),
+                })
+                .collect(),
+            start: date(days[0]),
+            end: date(days[days.len() - 1]) + Days::new(6),
+            strict,
+            filter: EventFilter::default(),
+            format: None,
+        };
+
+        handle(&UpstreamClient::new(UpstreamConfig::default()), &upstream).await
+    }
+
+    #[tokio::test]
+    async fn stitches_weekly_pages() {
+        let calendar = fetch_weeks(&[14, 21, 28], false).await.unwrap();
+        let days = calendar
+            .events
+            .iter()
+            .map(|event| event.date.format("%d.%m.").to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(days, ["14.10.", "21.10.", "28.10."]);
+        assert!(calendar.skipped.is_empty());
+    }
+
+    #[tokio::test]
+    async fn skips_broken_weeks() {
+        let calendar = fetch_weeks(&[1, 7, 14, 21, 28], false).await.unwrap();
+        assert_eq!(calendar.events.len(), 4);
+        assert!(
+            matches!(
+                calendar.skipped.as_slice(),
+                [ParseError::Page { start, .. }] if start.to_string() == "2024-10-07"
+            ),
+            "unexpected skipped {:?}",
+            calendar.skipped
+        );
+    }
+
+    #[tokio::test]
+    async fn fails_broken_weeks_when_strict() {
+        let err = fetch_weeks(&[1, 7, 14], true).await.unwrap_err();
+        assert!(matches!(err, Error::Parse(ParseError::Calendar { .. })));
+    }
+
+    #[tokio::test]
+    async fn fails_when_all_weeks_are_broken() {
+        let err = fetch_weeks(&[7], false).await.unwrap_err();
+        assert!(matches!(err, Error::Parse(ParseError::Calendar { .. })));
+    }
 }
@@ -1,12 +1,23 @@
+use std::fmt;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use axum::Router;
-use axum::extract::Request;
-use axum::http::{StatusCode, Uri};
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, StatusCode, Uri};
 use axum::middleware::{self, Next};
 use axum::response::{IntoResponse, Response};
-use chrono::{Datelike, Duration, Utc};
+use chrono::{Datelike, Duration, NaiveDate, Utc};
+use serde::de::{self, IntoDeserializer};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::calendar::Calendar;
+use crate::filter::{EventFilter, FilterQuery};
+use crate::format::Format;
+
+/// Parameters of [`RaplaBaseQuery`] that grant access to someone's calendar.
+pub const SECRET_PARAMETERS: [&str; 4] = ["key", "salt", "user", "file"];
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
@@ -20,7 +31,52 @@ struct RaplaQueryWithPage {
     #[serde(flatten)]
     base: RaplaBaseQuery,
     page: Option<String>,
+}
+
+/// Proxy-specific parameters, which are validated one by one to report all invalid ones.
+#[derive(Debug)]
+struct ProxyQuery {
     cutoff_date: Option<String>,
+    end_date: Option<String>,
+    past_weeks: Option<String>,
+    future_weeks: Option<String>,
+    strict: Option<String>,
+    format: Option<String>,
+}
+
+impl ProxyQuery {
+    /// Collects the parameters from a query string, reporting those given more than once.
+    fn from_query_string(invalid: &mut Vec<InvalidParameter>, query: &str) -> Self {
+        let pairs = serde_urlencoded::from_str::<Vec<(String, String)>>(query).unwrap_or_default();
+        let mut single = |name| {
+            let values = pairs
+                .iter()
+                .filter(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+                .collect::<Vec<_>>();
+            match values.as_slice() {
+                [] => None,
+                [value] => Some(value.to_string()),
+                _ => {
+                    invalid.push(InvalidParameter {
+                        name,
+                        value: values.join(", "),
+                        expected: "a single value",
+                    });
+                    None
+                }
+            }
+        };
+
+        Self {
+            cutoff_date: single("cutoff_date"),
+            end_date: single("end_date"),
+            past_weeks: single("past_weeks"),
+            future_weeks: single("future_weeks"),
+            strict: single("strict"),
+            format: single("format"),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -28,43 +84,187 @@ pub struct UpstreamUrlComponents {
     host: String,
     page: String,
     query: RaplaBaseQuery,
-    cutoff_date: Option<String>,
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+    past_weeks: Option<i64>,
+    future_weeks: Option<i64>,
+    strict: bool,
+    filter: EventFilter,
+    format: Option<Format>,
+}
+
+/// Server-side settings for resolving requests.
+#[derive(Debug, Clone)]
+pub struct ResolverConfig {
+    /// Rapla instances the proxy may fetch from.
+    pub hosts: Vec<String>,
+    /// Allowed hosts that ignore the `pages` parameter, so every week needs a request of its own.
+    pub weekly_hosts: Vec<String>,
+    /// The window of weeks fetched around today, unless the request asks for another.
+    pub past_weeks: i64,
+    pub future_weeks: i64,
+}
+
+impl Default for ResolverConfig {
+    fn default() -> Self {
+        Self {
+            hosts: vec![
+                UpstreamUrlComponents::DEFAULT_HOST.into(),
+                "rapla-ravensburg.dhbw.de".into(),
+            ],
+            weekly_hosts: vec!["rapla-ravensburg.dhbw.de".into()],
+            past_weeks: 52,
+            future_weeks: 52,
+        }
+    }
+}
+
+/// One upstream page of a calendar.
+#[derive(Debug, Clone)]
+pub struct UpstreamPage {
+    pub url: String,
+    /// Monday of the first week on the page.
+    pub start: NaiveDate,
 }
 
 #[derive(Debug, Clone)]
 pub struct UpstreamUrlExtension {
+    /// The whole calendar, which identifies it even if it is fetched in several pages.
     pub url: String,
-    pub start_year: i32,
+    pub pages: Vec<UpstreamPage>,
+    /// First and last day of events to keep, the pages may cover more.
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+    /// Fail on the first unparsable event instead of skipping it.
+    pub strict: bool,
+    /// Applied to the parsed calendar, not part of the upstream URL.
+    pub filter: EventFilter,
+    /// Overrides content negotiation when set.
+    pub format: Option<Format>,
+}
+
+/// A proxy-specific query parameter with a value we can't use.
+#[derive(Debug, Clone, Serialize)]
+pub struct InvalidParameter {
+    pub name: &'static str,
+    pub value: String,
+    pub expected: &'static str,
+}
+
+#[derive(Debug, Clone)]
+pub enum ResolveError {
+    /// Not a link to a Rapla calendar on an allowed host.
+    NotRapla,
+    /// A link to a Rapla calendar, but with proxy-specific parameters we can't use.
+    InvalidParameters(Vec<InvalidParameter>),
+}
+
+impl fmt::Display for InvalidParameter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}={:?}, expected {}",
+            self.name, self.value, self.expected
+        )
+    }
 }
 
-pub fn apply_middleware(router: Router) -> Router {
-    router.route_layer(middleware::from_fn(resolver_middleware))
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotRapla => write!(
+                f,
+                "Could not determine upstream URL, check your request URL"
+            ),
+            Self::InvalidParameters(_) => write!(f, "Invalid query parameters"),
+        }
+    }
 }
 
-async fn resolver_middleware(mut request: Request, next: Next) -> Response {
-    let Some(mut components) = UpstreamUrlComponents::from_request_uri(request.uri()) else {
-        return (
+impl std::error::Error for ResolveError {}
+
+impl ResolveError {
+    /// A `400 Bad Request` listing the invalid parameters, as JSON if that's what the client prefers.
+    pub fn to_response(&self, headers: &HeaderMap) -> Response {
+        let parameters = match self {
+            Self::NotRapla => &[][..],
+            Self::InvalidParameters(parameters) => parameters,
+        };
+
+        if Format::negotiate(None, headers) == Format::Json {
+            let body = json!({ "error": self.to_string(), "invalid_parameters": parameters });
+            return (
+                StatusCode::BAD_REQUEST,
+                [("content-type", "application/json")],
+                body.to_string(),
+            )
+                .into_response();
+        }
+
+        let mut body = format!("Error: {self}");
+        for parameter in parameters {
+            body.push_str(&format!("\n{parameter}"));
+        }
+        (
             StatusCode::BAD_REQUEST,
-            "Error: Could not determine upstream URL, check your request URL",
+            [("content-type", "text/plain")],
+            body,
         )
-            .into_response();
+            .into_response()
+    }
+}
+
+/// A `400 Bad Request` for requests that are malformed beyond single parameters.
+pub fn bad_request(message: &str) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        [("content-type", "text/plain")],
+        format!("Error: {message}"),
+    )
+        .into_response()
+}
+
+pub fn apply_middleware(router: Router, config: Arc<ResolverConfig>) -> Router {
+    router.route_layer(middleware::from_fn_with_state(config, resolver_middleware))
+}
+
+async fn resolver_middleware(
+    State(config): State<Arc<ResolverConfig>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let span = tracing::info_span!("resolve").entered();
+    let components = match UpstreamUrlComponents::from_request_uri(request.uri(), &config) {
+        Ok(components) => components,
+        Err(err) => {
+            tracing::debug!(%err, "can't resolve upstream");
+            return err.to_response(request.headers());
+        }
     };
 
-    if components.page == "ical" {
-        components.page = "calendar".into()
-    }
+    let upstream = match components.generate_url(&config) {
+        Ok(upstream) => upstream,
+        Err(err) => {
+            tracing::debug!(%err, "can't resolve upstream");
+            return err.to_response(request.headers());
+        }
+    };
+    tracing::debug!(
+        pages = upstream.pages.len(),
+        start = %upstream.start,
+        end = %upstream.end,
+        "resolved upstream",
+    );
+    drop(span);
 
-    request.extensions_mut().insert(components.generate_url());
+    request.extensions_mut().insert(upstream);
     next.run(request).await
 }
 
 impl UpstreamUrlComponents {
     const DEFAULT_HOST: &str = "rapla.dhbw.de";
-    const HOST_ALLOWLIST: &[&str] = &[Self::DEFAULT_HOST];
-    // TODO: Allow access to the Ravensburg instance once it supports the pages query parameter.
-    // const HOST_ALLOWLIST: &[&str] = &[Self::DEFAULT_HOST, "rapla-ravensburg.dhbw.de"];
 
-    pub fn from_request_uri(uri: &Uri) -> Option<Self> {
+    pub fn from_request_uri(uri: &Uri, config: &ResolverConfig) -> Result<Self, ResolveError> {
         // Try either:
         //  1. The request path, treating it as a URL (e.g. https://rapla.satoqz.net/https://rapla.dhbw.de/rapla/calendar).
         //  2. The request URL itself (e.g. https://rapla.satoqz.net/rapla/calendar).
@@ -74,65 +274,521 @@ impl UpstreamUrlComponents {
             .map(|path| path.as_str().trim_start_matches('/'))
             .and_then(|path| Uri::from_str(path).ok());
 
-        uri_in_path
+        match uri_in_path
             .as_ref()
-            .and_then(Self::from_simple_uri)
-            .or_else(|| Self::from_simple_uri(uri))
+            .map(|uri| Self::from_simple_uri(uri, config))
+        {
+            Some(Err(ResolveError::NotRapla)) | None => Self::from_simple_uri(uri, config),
+            Some(result) => result,
+        }
     }
 
-    pub fn from_simple_uri(uri: &Uri) -> Option<Self> {
+    /// The Rapla host, unless it's the one links without a host go to.
+    pub fn custom_host(&self) -> Option<&str> {
+        (self.host != Self::DEFAULT_HOST).then_some(self.host.as_str())
+    }
+
+    pub fn from_simple_uri(uri: &Uri, config: &ResolverConfig) -> Result<Self, ResolveError> {
         let host = uri.host().unwrap_or(Self::DEFAULT_HOST);
-        if !Self::HOST_ALLOWLIST.contains(&host) {
-            return None;
+        if !config.hosts.iter().any(|allowed| allowed == host) {
+            return Err(ResolveError::NotRapla);
         }
 
-        let query: RaplaQueryWithPage = serde_urlencoded::from_str(uri.query()?).ok()?;
-        let page = query.page.or_else(|| {
-            let path = uri.path();
-            path.starts_with("/rapla/").then(|| {
-                path.trim_start_matches("/rapla/")
-                    .trim_end_matches('/')
-                    .to_string()
+        // Only missing or broken Rapla parameters make this something else than a Rapla link.
+        let query: RaplaQueryWithPage = uri
+            .query()
+            .and_then(|query| serde_urlencoded::from_str(query).ok())
+            .ok_or(ResolveError::NotRapla)?;
+        let page = query
+            .page
+            .or_else(|| {
+                let path = uri.path();
+                path.starts_with("/rapla/").then(|| {
+                    path.trim_start_matches("/rapla/")
+                        .trim_end_matches('/')
+                        .to_string()
+                })
             })
-        })?;
+            .ok_or(ResolveError::NotRapla)?;
+
+        let page = if page == "ical" {
+            "calendar".into()
+        } else {
+            page
+        };
+
+        const DATE: &str = "a date as YYYY-MM-DD or DD.MM.YYYY, between 1970 and 9999";
+        const WEEKS: &str = "a number of weeks between 0 and 260";
+
+        let mut invalid = Vec::new();
+        let proxy_query =
+            ProxyQuery::from_query_string(&mut invalid, uri.query().unwrap_or_default());
+        let start_date = parse_parameter(
+            &mut invalid,
+            "cutoff_date",
+            proxy_query.cutoff_date,
+            DATE,
+            parse_date,
+        );
+        let end_date_value = proxy_query.end_date.clone().unwrap_or_default();
+        let end_date = parse_parameter(
+            &mut invalid,
+            "end_date",
+            proxy_query.end_date,
+            DATE,
+            parse_date,
+        );
+        let past_weeks = parse_parameter(
+            &mut invalid,
+            "past_weeks",
+            proxy_query.past_weeks,
+            WEEKS,
+            parse_weeks,
+        );
+        let future_weeks = parse_parameter(
+            &mut invalid,
+            "future_weeks",
+            proxy_query.future_weeks,
+            WEEKS,
+            parse_weeks,
+        );
+        let strict = parse_parameter(
+            &mut invalid,
+            "strict",
+            proxy_query.strict,
+            "true or false",
+            |value| value.parse().ok(),
+        );
+        let format = parse_format(&mut invalid, proxy_query.format);
+
+        if let (Some(start), Some(end)) = (start_date, end_date)
+            && end < start
+        {
+            invalid.push(InvalidParameter {
+                name: "end_date",
+                value: end_date_value,
+                expected: "a date on or after cutoff_date",
+            });
+        }
+
+        let filter = FilterQuery::from_query_string(uri.query().unwrap_or_default());
+        let filter = EventFilter::from_query(filter).unwrap_or_else(|mut filter_invalid| {
+            invalid.append(&mut filter_invalid);
+            EventFilter::default()
+        });
 
-        Some(UpstreamUrlComponents {
+        if !invalid.is_empty() {
+            return Err(ResolveError::InvalidParameters(invalid));
+        }
+
+        Ok(UpstreamUrlComponents {
             host: host.to_string(),
             page,
             query: query.base,
-            cutoff_date: query.cutoff_date,
+            start_date,
+            end_date,
+            past_weeks,
+            future_weeks,
+            strict: strict.unwrap_or(false),
+            filter,
+            format,
         })
     }
 
-    pub fn generate_url(self) -> UpstreamUrlExtension {
-        // These don't need to be 100% accurate.
-        const WEEKS_TWO_YEARS: usize = 104;
-        const DAYS_ONE_YEAR: i64 = 365;
+    /// Fails if the window of weeks doesn't fit into the range of dates we can represent.
+    pub fn generate_url(
+        self,
+        config: &ResolverConfig,
+    ) -> Result<UpstreamUrlExtension, ResolveError> {
+        // Rapla gets slow with many pages, and nobody needs years of lectures.
+        const MAX_WEEKS: i64 = 260;
 
-        // Parse cutoff_date if provided, otherwise use year_ago
-        let cutoff = self
-            .cutoff_date
-            .and_then(|date_str| {
-                chrono::NaiveDate::parse_from_str(&date_str, "%Y-%m-%d")
-                    .map(|date| date.and_hms_opt(0, 0, 0).unwrap().and_utc())
-                    .ok()
-            })
-            .unwrap_or(Utc::now() - Duration::try_days(DAYS_ONE_YEAR).unwrap());
+        let today = Utc::now().date_naive();
+        let add_weeks = |date: NaiveDate, weeks: i64| {
+            Duration::try_weeks(weeks).and_then(|weeks| date.checked_add_signed(weeks))
+        };
+        let out_of_range = |name, value: String| {
+            ResolveError::InvalidParameters(vec![InvalidParameter {
+                name,
+                value,
+                expected: "a date further away from the limits of the calendar",
+            }])
+        };
+
+        let start = match self.start_date {
+            Some(start) => start,
+            None => {
+                let weeks = self.past_weeks.unwrap_or(config.past_weeks);
+                add_weeks(today, -weeks)
+                    .ok_or_else(|| out_of_range("past_weeks", weeks.to_string()))?
+            }
+        };
+        let end = match (self.end_date, self.start_date, self.future_weeks) {
+            (Some(end), _, _) => end,
+            // An explicit start without an explicit end shifts the default window.
+            (None, Some(start), None) => add_weeks(start, config.past_weeks + config.future_weeks)
+                .ok_or_else(|| out_of_range("cutoff_date", start.to_string()))?,
+            (None, _, future_weeks) => {
+                let weeks = future_weeks.unwrap_or(config.future_weeks);
+                add_weeks(today, weeks)
+                    .ok_or_else(|| out_of_range("future_weeks", weeks.to_string()))?
+            }
+        };
 
-        let url = format!(
-            "https://{}/rapla/{}?day={}&month={}&year={}&pages={WEEKS_TWO_YEARS}&{}",
+        // Without an explicit start, the end may still be before the default one.
+        if end < start {
+            return Err(ResolveError::InvalidParameters(vec![InvalidParameter {
+                name: "end_date",
+                value: end.to_string(),
+                expected: "a date on or after the first week shown, or a cutoff_date",
+            }]));
+        }
+
+        // Start at Monday, the parser expects the year of the first week's Monday.
+        let monday = start
+            .checked_sub_signed(Duration::days(
+                start.weekday().num_days_from_monday().into(),
+            ))
+            .ok_or_else(|| out_of_range("cutoff_date", start.to_string()))?;
+        let weeks = ((end - monday).num_days() / 7 + 1).clamp(1, MAX_WEEKS);
+
+        let url = self.page_url(monday, Some(weeks));
+
+        let pages = if config.weekly_hosts.contains(&self.host) {
+            (0..weeks)
+                .map(|week| {
+                    let date = add_weeks(monday, week)
+                        .ok_or_else(|| out_of_range("cutoff_date", start.to_string()))?;
+                    Ok(UpstreamPage {
+                        url: self.page_url(date, None),
+                        start: date,
+                    })
+                })
+                .collect::<Result<_, _>>()?
+        } else {
+            vec![UpstreamPage {
+                url: url.clone(),
+                start: monday,
+            }]
+        };
+
+        Ok(UpstreamUrlExtension {
+            url,
+            pages,
+            start,
+            end,
+            strict: self.strict,
+            filter: self.filter,
+            format: self.format,
+        })
+    }
+
+    fn page_url(&self, date: NaiveDate, pages: Option<i64>) -> String {
+        let pages = pages
+            .map(|pages| format!("&pages={pages}"))
+            .unwrap_or_default();
+        format!(
+            "https://{}/rapla/{}?day={}&month={}&year={}{pages}&{}",
             self.host,
             self.page,
-            cutoff.day(),
-            cutoff.month(),
-            cutoff.year(),
+            date.day(),
+            date.month(),
+            date.year(),
             // There's no reason this should fail, we already parsed it in the first place.
-            serde_urlencoded::to_string(self.query).unwrap()
+            serde_urlencoded::to_string(&self.query).unwrap()
+        )
+    }
+}
+
+/// Parses a present parameter, recording it as invalid if that fails.
+fn parse_parameter<T>(
+    invalid: &mut Vec<InvalidParameter>,
+    name: &'static str,
+    value: Option<String>,
+    expected: &'static str,
+    parse: impl FnOnce(&str) -> Option<T>,
+) -> Option<T> {
+    let value = value?;
+    let parsed = parse(value.trim());
+    if parsed.is_none() {
+        invalid.push(InvalidParameter {
+            name,
+            value,
+            expected,
+        });
+    }
+    parsed
+}
+
+/// Parses the `format` parameter, recording it as invalid if that fails.
+pub fn parse_format(invalid: &mut Vec<InvalidParameter>, value: Option<String>) -> Option<Format> {
+    parse_parameter(
+        invalid,
+        "format",
+        value,
+        "one of ics, json, jcal, xcal or html",
+        |value| {
+            Format::deserialize(IntoDeserializer::<de::value::Error>::into_deserializer(
+                value,
+            ))
+            .ok()
+        },
+    )
+}
+
+fn parse_date(date: &str) -> Option<NaiveDate> {
+    // ISO 8601, and the German format people are used to from Rapla itself.
+    ["%Y-%m-%d", "%d.%m.%Y"]
+        .into_iter()
+        .find_map(|format| NaiveDate::parse_from_str(date, format).ok())
+        // Rapla has no use for other years, and the window around them must stay representable.
+        .filter(|date| (1970..=9999).contains(&date.year()))
+}
+
+fn parse_weeks(weeks: &str) -> Option<i64> {
+    weeks.parse().ok().filter(|weeks| (0..=260).contains(weeks))
+}
+
+impl UpstreamUrlExtension {
+    /// Applies the parts of the request that aren't part of the upstream URL,
+    /// i.e. the exact window and the filter.
+    pub fn apply(&self, calendar: &mut Calendar) {
+        calendar
+            .events
+            .retain(|event| (self.start..=self.end).contains(&event.date));
+        self.filter.apply(calendar);
+    }
+
+    /// Identifies the parsed calendar, which depends on more than just the upstream URL.
+    pub fn cache_key(&self) -> String {
+        if self.strict {
+            format!("{} (strict)", self.url)
+        } else {
+            self.url.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::to_bytes;
+    use axum::http::HeaderValue;
+    use axum::http::header::ACCEPT;
+
+    use super::*;
+
+    fn invalid_parameters(uri: &str) -> Vec<InvalidParameter> {
+        match UpstreamUrlComponents::from_request_uri(
+            &uri.parse().unwrap(),
+            &ResolverConfig::default(),
+        ) {
+            Err(ResolveError::InvalidParameters(invalid)) => invalid,
+            other => panic!("expected invalid parameters, got {other:?}"),
+        }
+    }
+
+    async fn body(response: Response) -> String {
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn parses_dates() {
+        let date = NaiveDate::from_ymd_opt(2024, 10, 1);
+        assert_eq!(parse_date("2024-10-01"), date);
+        assert_eq!(parse_date("01.10.2024"), date);
+        assert_eq!(parse_date("1.10.2024"), date);
+        assert_eq!(parse_date("2024-02-30"), None);
+        assert_eq!(parse_date("10/01/2024"), None);
+        assert_eq!(parse_date(""), None);
+        assert_eq!(parse_date("1969-12-31"), None);
+        assert_eq!(parse_date("-2024-10-01"), None);
+        assert_eq!(parse_date("01.10.10000"), None);
+    }
+
+    #[test]
+    fn parses_weeks() {
+        assert_eq!(parse_weeks("0"), Some(0));
+        assert_eq!(parse_weeks("260"), Some(260));
+        assert_eq!(parse_weeks("261"), None);
+        assert_eq!(parse_weeks("-1"), None);
+        assert_eq!(parse_weeks("4.5"), None);
+    }
+
+    #[test]
+    fn trims_parameters() {
+        let mut invalid = Vec::new();
+        let weeks = parse_parameter(
+            &mut invalid,
+            "past_weeks",
+            Some(" 4 ".into()),
+            "",
+            parse_weeks,
         );
+        assert_eq!(weeks, Some(4));
+        assert!(invalid.is_empty());
+    }
 
-        UpstreamUrlExtension {
-            url,
-            start_year: cutoff.year(),
+    #[test]
+    fn reports_all_invalid_parameters() {
+        let invalid = invalid_parameters(
+            "/rapla/calendar?key=a&salt=b&cutoff_date=tomorrow&past_weeks=1000&strict=yes&format=pdf",
+        );
+        let invalid = invalid
+            .iter()
+            .map(|parameter| (parameter.name, parameter.value.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            invalid,
+            [
+                ("cutoff_date", "tomorrow"),
+                ("past_weeks", "1000"),
+                ("strict", "yes"),
+                ("format", "pdf"),
+            ]
+        );
+    }
+
+    #[test]
+    fn accepts_repeated_filters() {
+        let uri = "/rapla/calendar?key=a&salt=b&include=Mathe&include=Labor&exclude=Tutorium"
+            .parse()
+            .unwrap();
+        let config = ResolverConfig::default();
+        let upstream = UpstreamUrlComponents::from_request_uri(&uri, &config)
+            .unwrap()
+            .generate_url(&config)
+            .unwrap();
+        assert!(!upstream.filter.is_empty());
+        assert!(!upstream.url.contains("include") && !upstream.url.contains("exclude"));
+    }
+
+    #[test]
+    fn rejects_dates_at_the_limits() {
+        let config = ResolverConfig {
+            weekly_hosts: vec![UpstreamUrlComponents::DEFAULT_HOST.into()],
+            ..ResolverConfig::default()
+        };
+        for date in ["-262143-01-01", "%2B262142-12-31"] {
+            let uri = format!("/rapla/calendar?key=a&salt=b&cutoff_date={date}")
+                .parse()
+                .unwrap();
+            let result = UpstreamUrlComponents::from_request_uri(&uri, &config)
+                .and_then(|upstream| upstream.generate_url(&config));
+            assert!(
+                matches!(
+                    &result,
+                    Err(ResolveError::InvalidParameters(invalid))
+                        if invalid.iter().any(|parameter| parameter.name == "cutoff_date")
+                ),
+                "unexpected result for {date}: {result:?}"
+            );
         }
     }
+
+    #[test]
+    fn reports_repeated_parameters() {
+        let invalid = invalid_parameters(
+            "/rapla/calendar?key=a&salt=b&cutoff_date=2024-10-01&cutoff_date=2024-11-01&strict=true",
+        );
+        let [parameter] = invalid.as_slice() else {
+            panic!("expected one invalid parameter, got {invalid:?}");
+        };
+        assert_eq!(parameter.name, "cutoff_date");
+        assert_eq!(parameter.value, "2024-10-01, 2024-11-01");
+        assert_eq!(parameter.expected, "a single value");
+    }
+
+    #[test]
+    fn rejects_links_without_rapla_parameters() {
+        let config = ResolverConfig::default();
+        for uri in [
+            "/rapla/calendar?cutoff_date=2024-10-01",
+            "/rapla/calendar?key=a&cutoff_date=2024-10-01",
+            "/rapla/calendar?user=a&past_weeks=many",
+        ] {
+            let result = UpstreamUrlComponents::from_request_uri(&uri.parse().unwrap(), &config);
+            assert!(
+                matches!(result, Err(ResolveError::NotRapla)),
+                "unexpected result for {uri}: {result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_end_before_start() {
+        let invalid = invalid_parameters(
+            "/rapla/calendar?key=a&salt=b&cutoff_date=2024-10-01&end_date=30.09.2024",
+        );
+        let [parameter] = invalid.as_slice() else {
+            panic!("expected one invalid parameter, got {invalid:?}");
+        };
+        assert_eq!(parameter.name, "end_date");
+        assert_eq!(parameter.value, "30.09.2024");
+        assert_eq!(parameter.expected, "a date on or after cutoff_date");
+    }
+
+    #[test]
+    fn rejects_end_before_default_start() {
+        let config = ResolverConfig::default();
+        let uri = "/rapla/calendar?key=a&salt=b&end_date=01.01.2000"
+            .parse()
+            .unwrap();
+        let result = UpstreamUrlComponents::from_request_uri(&uri, &config)
+            .and_then(|upstream| upstream.generate_url(&config));
+        let Err(ResolveError::InvalidParameters(invalid)) = result else {
+            panic!("expected invalid parameters, got {result:?}");
+        };
+        assert_eq!(invalid[0].name, "end_date");
+        assert_eq!(invalid[0].value, "2000-01-01");
+    }
+
+    #[tokio::test]
+    async fn lists_invalid_parameters_in_body() {
+        let err = ResolveError::InvalidParameters(vec![
+            InvalidParameter {
+                name: "past_weeks",
+                value: "many".into(),
+                expected: "a number of weeks between 0 and 260",
+            },
+            InvalidParameter {
+                name: "strict",
+                value: "yes".into(),
+                expected: "true or false",
+            },
+        ]);
+
+        let response = err.to_response(&HeaderMap::new());
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body(response).await,
+            "Error: Invalid query parameters\n\
+             past_weeks=\"many\", expected a number of weeks between 0 and 260\n\
+             strict=\"yes\", expected true or false"
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+        let response = err.to_response(&headers);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            response.headers()["content-type"],
+            HeaderValue::from_static("application/json")
+        );
+        let body = serde_json::from_str::<serde_json::Value>(&body(response).await).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "error": "Invalid query parameters",
+                "invalid_parameters": [
+                    {
+                        "name": "past_weeks",
+                        "value": "many",
+                        "expected": "a number of weeks between 0 and 260",
+                    },
+                    { "name": "strict", "value": "yes", "expected": "true or false" },
+                ],
+            })
+        );
+    }
 }
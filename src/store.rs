@@ -0,0 +1,78 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::calendar::Calendar;
+
+/// A cached calendar in the shape it is written to disk.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoredCalendar {
+    pub key: String,
+    pub calendar: Calendar,
+    pub timestamp: SystemTime,
+    pub last_modified: SystemTime,
+    pub failed_refresh: Option<SystemTime>,
+}
+
+/// Keeps cached calendars as one JSON file per cache key in a directory, so
+/// they survive restarts.
+#[derive(Debug, Clone)]
+pub struct DiskStore {
+    dir: PathBuf,
+}
+
+impl DiskStore {
+    pub fn open(dir: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir
+            .join(format!("{:016x}.json", xxh3_64(key.as_bytes())))
+    }
+
+    /// Reads all stored calendars, removing those that are unreadable or older than `retention`.
+    pub fn load(&self, retention: Duration) -> io::Result<Vec<StoredCalendar>> {
+        let mut calendars = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|extension| extension != "json") {
+                continue;
+            }
+
+            let calendar = fs::read(&path)
+                .ok()
+                .and_then(|bytes| serde_json::from_slice::<StoredCalendar>(&bytes).ok())
+                .filter(|calendar| calendar.timestamp.elapsed().unwrap_or_default() < retention);
+
+            match calendar {
+                Some(calendar) => calendars.push(calendar),
+                None => {
+                    let _ = fs::remove_file(&path);
+                }
+            }
+        }
+
+        calendars.sort_by_key(|calendar| calendar.timestamp);
+        Ok(calendars)
+    }
+
+    pub async fn save(&self, calendar: &StoredCalendar) -> io::Result<()> {
+        let path = self.path(&calendar.key);
+        let temp_path = path.with_extension("tmp");
+        let bytes = serde_json::to_vec(calendar)?;
+
+        // Write to a temporary file first, so readers never see half a file.
+        tokio::fs::write(&temp_path, bytes).await?;
+        tokio::fs::rename(&temp_path, &path).await
+    }
+
+    pub fn remove(&self, key: &str) {
+        let _ = fs::remove_file(self.path(key));
+    }
+}
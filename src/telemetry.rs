@@ -0,0 +1,231 @@
+use std::env;
+
+use axum::http::HeaderMap;
+use opentelemetry::propagation::TextMapCompositePropagator;
+use opentelemetry_http::{HeaderExtractor, HeaderInjector};
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Sets up OTLP trace export if an endpoint is configured through the standard
+/// `OTEL_EXPORTER_OTLP_ENDPOINT` or `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`
+/// variables, which the exporter reads along with the other `OTEL_*` settings.
+///
+/// The provider has to be shut down on exit to send the remaining spans.
+pub fn tracer_provider() -> Option<SdkTracerProvider> {
+    let configured = [
+        "OTEL_EXPORTER_OTLP_ENDPOINT",
+        "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+    ]
+    .into_iter()
+    .any(|key| env::var_os(key).is_some_and(|value| !value.is_empty()));
+    if !configured {
+        return None;
+    }
+
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .build()
+        .unwrap_or_else(|err| {
+            eprintln!("Invalid OTLP exporter configuration: {err}");
+            std::process::exit(1);
+        });
+
+    // `OTEL_SERVICE_NAME` and `OTEL_RESOURCE_ATTRIBUTES` take precedence.
+    let mut resource = Resource::builder();
+    if env::var_os("OTEL_SERVICE_NAME").is_none() {
+        resource = resource.with_service_name(env!("CARGO_PKG_NAME"));
+    }
+
+    set_propagator();
+    Some(
+        SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(resource.build())
+            .build(),
+    )
+}
+
+/// Only W3C trace context is supported, i.e. the `traceparent` and `tracestate` headers.
+fn set_propagator() {
+    opentelemetry::global::set_text_map_propagator(TextMapCompositePropagator::new(vec![
+        Box::new(TraceContextPropagator::new()),
+    ]));
+}
+
+/// Continues the trace of the incoming request's `traceparent` header in `span`.
+pub fn continue_trace(span: &Span, headers: &HeaderMap) {
+    let context = opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(headers))
+    });
+    // Fails only if trace export is disabled, in which case there's nothing to continue.
+    let _ = span.set_parent(context);
+}
+
+/// Adds the `traceparent` header of the current span to an upstream request.
+pub fn propagate_trace(headers: &mut HeaderMap) {
+    let context = Span::current().context();
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(headers));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use axum::Router;
+    use axum::body::Body;
+    use axum::extract::{Request, State};
+    use axum::middleware::{self, Next};
+    use axum::response::Response;
+    use axum::routing::get;
+    use opentelemetry::trace::{SpanId, TraceId, TracerProvider};
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SpanData};
+    use tower::ServiceExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::cache::{CacheConfig, CalendarCache};
+    use crate::logging::Redaction;
+    use crate::proxy::{Proxy, UpstreamClient, UpstreamConfig};
+    use crate::resolver::UpstreamUrlExtension;
+
+    const RAPLA_PAGE: &str = r#"<html><head><title>TINF22B</title></head><body><div class="calendar"><table class="week_table"><tbody><tr><th class="week_number">KW 40</th><td class="week_header"><nobr>Mo 30.09.</nobr></td></tr><tr><td class="week_block"><a href="/rapla/eventinfo?id=abc">08:00&nbsp;-10:00<br>Mathe<br></a></td></tr></tbody></table></div></body></html>"#;
+
+    /// Stands in for Rapla, remembering the `traceparent` header of each request.
+    async fn rapla_stand_in() -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+        let traceparents = Arc::new(Mutex::new(Vec::new()));
+        let app =
+            Router::new()
+                .route(
+                    "/rapla/calendar",
+                    get(
+                        |State(traceparents): State<Arc<Mutex<Vec<String>>>>,
+                         headers: HeaderMap| async move {
+                            let traceparent = headers
+                                .get("traceparent")
+                                .and_then(|value| value.to_str().ok())
+                                .unwrap_or_default();
+                            traceparents.lock().unwrap().push(traceparent.to_string());
+                            axum::response::Html(RAPLA_PAGE)
+                        },
+                    ),
+                )
+                .with_state(traceparents.clone());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (address, traceparents)
+    }
+
+    /// Sends the resolved pages to the stand-in instead of the real Rapla host.
+    async fn redirect_upstream(
+        State(address): State<SocketAddr>,
+        mut request: Request,
+        next: Next,
+    ) -> Response {
+        if let Some(upstream) = request.extensions_mut().get_mut::<UpstreamUrlExtension>() {
+            for page in &mut upstream.pages {
+                page.url =
+                    page.url
+                        .replacen("https://rapla.dhbw.de", &format!("http://{address}"), 1);
+            }
+        }
+        next.run(request).await
+    }
+
+    fn span<'a>(spans: &'a [SpanData], name: &str) -> &'a SpanData {
+        spans
+            .iter()
+            .find(|span| span.name == name)
+            .unwrap_or_else(|| panic!("no {name} span in {spans:#?}"))
+    }
+
+    #[tokio::test]
+    async fn exports_request_spans_and_propagates_trace() {
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        set_propagator();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        // The current-thread runtime keeps spawned tasks on this thread, and in this subscriber.
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let (address, traceparents) = rapla_stand_in().await;
+        let cache = CalendarCache::new(
+            CacheConfig {
+                ttl: Duration::from_secs(60),
+                error_ttl: Duration::from_secs(60),
+                retention: Duration::from_secs(60),
+                max_capacity: 1,
+            },
+            None,
+        )
+        .await;
+        let proxy = Proxy::new(UpstreamClient::new(UpstreamConfig::default()), cache);
+
+        let router = crate::proxy::apply_routes(Router::new(), proxy);
+        let router = router.route_layer(middleware::from_fn_with_state(address, redirect_upstream));
+        let router = crate::resolver::apply_middleware(router, Arc::default());
+        let router = crate::logging::apply_middleware(router, Redaction::Hashed);
+
+        let incoming_trace = "4bf92f3577b34da6a3ce929d0e0e4736";
+        let incoming_span = "00f067aa0ba902b7";
+        let request = Request::get("/rapla/calendar?key=abc&salt=def&cutoff_date=2024-09-30")
+            .header(
+                "traceparent",
+                format!("00-{incoming_trace}-{incoming_span}-01"),
+            )
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert!(response.status().is_success(), "{response:?}");
+        drop(response);
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let ids = spans
+            .iter()
+            .map(|span| (span.span_context.span_id(), span.name.as_ref()))
+            .collect::<HashMap<SpanId, &str>>();
+        let parent = |name| {
+            let span = span(&spans, name);
+            assert_eq!(
+                span.span_context.trace_id(),
+                TraceId::from_hex(incoming_trace).unwrap(),
+                "{name} span should continue the incoming trace"
+            );
+            ids.get(&span.parent_span_id).copied()
+        };
+
+        let request_span = span(&spans, "request");
+        assert_eq!(
+            request_span.parent_span_id,
+            SpanId::from_hex(incoming_span).unwrap()
+        );
+        assert!(request_span.parent_span_is_remote);
+        assert_eq!(parent("resolve"), Some("request"));
+        assert_eq!(parent("cache"), Some("request"));
+        assert_eq!(parent("fetch"), Some("cache"));
+        assert_eq!(parent("upstream"), Some("fetch"));
+        assert_eq!(parent("parse"), Some("fetch"));
+
+        let upstream = span(&spans, "upstream");
+        assert_eq!(
+            *traceparents.lock().unwrap(),
+            [format!(
+                "00-{incoming_trace}-{}-01",
+                upstream.span_context.span_id()
+            )]
+        );
+    }
+}